@@ -1,9 +1,41 @@
 use criterion::{Criterion, Throughput, criterion_group, criterion_main};
-use my_app::arguments::checksum::process_checksum_internal;
+use my_app::arguments::checksum::{
+    Algorithm, ChecksumOptions, Pipeline, process_checksum_input, process_checksum_internal,
+};
 use std::fs;
-use std::io::{Cursor, Sink};
+use std::io::{Cursor, Read, Sink, Write};
 use std::path::PathBuf;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// Simulates a slow producer on stdin: every read returns at most 64 KiB and
+/// takes at least 20µs, like data arriving from a network or a decompressor.
+struct SlowReader<'a> {
+    data: &'a [u8],
+}
+
+impl Read for SlowReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = Instant::now();
+        let len = buf.len().min(64 * 1024).min(self.data.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data = &self.data[len..];
+        while start.elapsed() < Duration::from_micros(20) {
+            std::hint::spin_loop();
+        }
+        Ok(len)
+    }
+}
+
+/// Options with `buffers` read-ahead buffers; one disables the pipeline.
+fn with_buffers(buffers: usize) -> ChecksumOptions {
+    ChecksumOptions {
+        pipeline: Pipeline {
+            buffers,
+            ..Pipeline::default()
+        },
+        ..Algorithm::Sha256.into()
+    }
+}
 
 fn bench_checksum(c: &mut Criterion) {
     let mut group = c.benchmark_group("checksum");
@@ -27,7 +59,8 @@ fn bench_checksum(c: &mut Criterion) {
         b.iter(|| {
             let reader = Cursor::new(&content);
             let mut writer = Sink::default();
-            process_checksum_internal(reader, "bench_test", &mut writer).unwrap();
+            process_checksum_internal(reader, "bench_test", &mut writer, &Algorithm::Sha256.into())
+                .unwrap();
         })
     });
 
@@ -36,10 +69,32 @@ fn bench_checksum(c: &mut Criterion) {
             let reader = Cursor::new(&content);
             let mut writer = Sink::default();
             // Simulate stdin by passing a cursor ("-") as filename convention
-            process_checksum_internal(reader, "-", &mut writer).unwrap();
+            process_checksum_internal(reader, "-", &mut writer, &Algorithm::Sha256.into()).unwrap();
         })
     });
 
+    for (name, buffers) in [("single_thread", 1), ("pipelined", 4)] {
+        let options = with_buffers(buffers);
+
+        group.bench_function(format!("slow_stdin_checksum_{}", name), |b| {
+            b.iter(|| {
+                let reader = SlowReader { data: &content };
+                process_checksum_internal(reader, "-", Sink::default(), &options).unwrap();
+            })
+        });
+
+        group.bench_function(format!("os_pipe_checksum_{}", name), |b| {
+            b.iter(|| {
+                let (reader, mut writer) = std::io::pipe().unwrap();
+                let content = &content;
+                std::thread::scope(|scope| {
+                    scope.spawn(move || writer.write_all(content).unwrap());
+                    process_checksum_internal(reader, "-", Sink::default(), &options).unwrap();
+                });
+            })
+        });
+    }
+
     group.bench_function("file_process_checksum", |b| {
         b.iter(|| {
             let mut writer = Sink::default();
@@ -48,13 +103,60 @@ fn bench_checksum(c: &mut Criterion) {
                 &files,
                 &mut writer,
                 |mut reader, path_display, writer| {
-                    process_checksum_internal(&mut reader, path_display, writer)
+                    process_checksum_internal(
+                        &mut reader,
+                        path_display,
+                        writer,
+                        &Algorithm::Sha256.into(),
+                    )
                 },
             )
             .unwrap();
         })
     });
 
+    group.bench_function("file_process_checksum_mmap", |b| {
+        b.iter(|| {
+            let mut writer = Sink::default();
+            let files = vec![large_file_path.clone()];
+            my_app::utils::process_inputs(&files, &mut writer, |input, path_display, writer| {
+                process_checksum_input(input, path_display, writer, &Algorithm::Sha256.into())
+            })
+            .unwrap();
+        })
+    });
+
+    group.bench_function("file_blake3_streaming", |b| {
+        b.iter(|| {
+            let mut writer = Sink::default();
+            let files = vec![large_file_path.clone()];
+            my_app::utils::process_inputs(
+                &files,
+                &mut writer,
+                |mut reader, path_display, writer| {
+                    process_checksum_internal(
+                        &mut reader,
+                        path_display,
+                        writer,
+                        &Algorithm::Blake3.into(),
+                    )
+                },
+            )
+            .unwrap();
+        })
+    });
+
+    group.bench_function("file_blake3_mmap_parallel", |b| {
+        b.iter(|| {
+            let mut writer = Sink::default();
+            let files = vec![large_file_path.clone()];
+            my_app::utils::process_inputs(&files, &mut writer, |input, path_display, writer| {
+                process_checksum_input(input, path_display, writer, &Algorithm::Blake3.into())
+            })
+            .unwrap();
+        })
+    });
+
     group.finish();
 }
 
@@ -1,10 +1,711 @@
 use anyhow::Result;
-use sha2::{Digest, Sha256};
+use hmac::KeyInit;
+use sha2::Digest;
+use sha2::digest::common::hazmat::{SerializableState, SerializedState};
 use std::io::{self, Read, Write};
 
+pub use encoding::Encoding;
+pub use key::SecretKey;
+pub use template::{FileStat, Template};
+
+use crate::arguments::signature;
 use crate::traits::CommandArg;
-use crate::utils::process_inputs;
-use std::path::PathBuf;
+use crate::utils::{
+    Input, InputOptions, SilentFailure, SymlinkMode, Trouble, process_inputs,
+    process_inputs_parallel,
+};
+use memmap2::Mmap;
+use std::cell::Cell;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::thread;
+
+pub mod cache;
+pub mod cdc;
+pub mod check;
+pub mod checkpoint;
+pub mod diff;
+pub mod dupes;
+pub mod encoding;
+pub mod escape;
+pub mod key;
+pub mod range;
+pub mod template;
+pub mod tree;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
+
+/// Regular files at least this large are hashed through a memory map, and on
+/// all cores with BLAKE3's multi-threaded tree mode.
+pub const MMAP_THRESHOLD: u64 = 1024 * 1024;
+
+/// Bytes of a mapped file hashed between checks that the file did not shrink.
+const MMAP_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Default size of each read-ahead buffer, see `Pipeline`.
+pub const DEFAULT_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Default number of read-ahead buffers, see `Pipeline`.
+pub const DEFAULT_BUFFERS: usize = 4;
+
+/// Hash algorithms supported by the checksum command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Algorithm {
+    /// SHA-224, as printed by `sha224sum`.
+    #[value(name = "sha224")]
+    Sha224,
+    /// SHA-256, as printed by `sha256sum`.
+    #[default]
+    #[value(name = "sha256")]
+    Sha256,
+    /// SHA-384, as printed by `sha384sum`.
+    #[value(name = "sha384")]
+    Sha384,
+    /// SHA-512, as printed by `sha512sum`.
+    #[value(name = "sha512")]
+    Sha512,
+    /// SHA-512 truncated to 256 bits.
+    #[value(name = "sha512/256", alias = "sha512-256")]
+    Sha512_256,
+    /// SHA3-224.
+    #[value(name = "sha3-224")]
+    Sha3_224,
+    /// SHA3-256.
+    #[value(name = "sha3-256")]
+    Sha3_256,
+    /// SHA3-384.
+    #[value(name = "sha3-384")]
+    Sha3_384,
+    /// SHA3-512.
+    #[value(name = "sha3-512")]
+    Sha3_512,
+    /// BLAKE3, as printed by `b3sum`.
+    #[value(name = "blake3")]
+    Blake3,
+    /// 64-bit XXH3 (non-cryptographic).
+    #[value(name = "xxh3-64", alias = "xxh3")]
+    Xxh3_64,
+    /// 128-bit XXH3 (non-cryptographic).
+    #[value(name = "xxh3-128", alias = "xxh128")]
+    Xxh3_128,
+    /// CRC-32C, the Castagnoli CRC (non-cryptographic).
+    #[value(name = "crc32c")]
+    Crc32c,
+    /// MD5, as printed by `md5sum` (broken; requires `--allow-weak-hashes`).
+    #[value(name = "md5")]
+    Md5,
+    /// SHA-1, as printed by `sha1sum` (broken; requires `--allow-weak-hashes`).
+    #[value(name = "sha1")]
+    Sha1,
+}
+
+impl Algorithm {
+    /// Creates a fresh hasher for this algorithm.
+    pub fn hasher(self) -> Box<dyn Hasher> {
+        match self {
+            Algorithm::Sha224 => Box::new(DigestHasher(sha2::Sha224::new())),
+            Algorithm::Sha256 => Box::new(DigestHasher(sha2::Sha256::new())),
+            Algorithm::Sha384 => Box::new(DigestHasher(sha2::Sha384::new())),
+            Algorithm::Sha512 => Box::new(DigestHasher(sha2::Sha512::new())),
+            Algorithm::Sha512_256 => Box::new(DigestHasher(sha2::Sha512_256::new())),
+            Algorithm::Sha3_224 => Box::new(DigestHasher(sha3::Sha3_224::new())),
+            Algorithm::Sha3_256 => Box::new(DigestHasher(sha3::Sha3_256::new())),
+            Algorithm::Sha3_384 => Box::new(DigestHasher(sha3::Sha3_384::new())),
+            Algorithm::Sha3_512 => Box::new(DigestHasher(sha3::Sha3_512::new())),
+            Algorithm::Blake3 => Box::new(Blake3Hasher(blake3::Hasher::new())),
+            Algorithm::Xxh3_64 => Box::new(Xxh3_64Hasher(xxhash_rust::xxh3::Xxh3::new())),
+            Algorithm::Xxh3_128 => Box::new(Xxh3_128Hasher(xxhash_rust::xxh3::Xxh3::new())),
+            Algorithm::Crc32c => Box::new(Crc32cHasher(0)),
+            Algorithm::Md5 => Box::new(DigestHasher(md5::Md5::new())),
+            Algorithm::Sha1 => Box::new(DigestHasher(sha1::Sha1::new())),
+        }
+    }
+
+    /// Creates a fresh keyed hasher: HMAC for SHA-256 and SHA-512, and
+    /// BLAKE3's keyed mode. Fails for other algorithms and for BLAKE3 keys
+    /// that are not exactly 32 bytes long.
+    pub fn keyed_hasher(self, key: &SecretKey) -> io::Result<Box<dyn Hasher>> {
+        match self {
+            Algorithm::Sha256 => Ok(Box::new(HmacHasher(
+                hmac::Hmac::<sha2::Sha256>::new_from_slice(key.bytes())
+                    .expect("HMAC accepts keys of any length"),
+            ))),
+            Algorithm::Sha512 => Ok(Box::new(HmacHasher(
+                hmac::Hmac::<sha2::Sha512>::new_from_slice(key.bytes())
+                    .expect("HMAC accepts keys of any length"),
+            ))),
+            Algorithm::Blake3 => Ok(Box::new(Blake3Hasher(blake3::Hasher::new_keyed(
+                &key.blake3_key()?,
+            )))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} does not support keyed hashing", self.name()),
+            )),
+        }
+    }
+
+    /// Recreates an unkeyed hasher from the bytes `Hasher::save_state`
+    /// returned. Returns `None` if this algorithm's state can't be saved or
+    /// `state` is not a valid one.
+    pub fn restore_hasher(self, state: &[u8]) -> Option<Box<dyn Hasher>> {
+        fn restore<D>(state: &[u8]) -> Option<Box<dyn Hasher>>
+        where
+            D: Digest + SerializableState + Send + 'static,
+        {
+            let state = SerializedState::<D>::try_from(state).ok()?;
+            Some(Box::new(DigestHasher(D::deserialize(&state).ok()?)))
+        }
+        match self {
+            Algorithm::Sha224 => restore::<sha2::Sha224>(state),
+            Algorithm::Sha256 => restore::<sha2::Sha256>(state),
+            Algorithm::Sha384 => restore::<sha2::Sha384>(state),
+            Algorithm::Sha512 => restore::<sha2::Sha512>(state),
+            Algorithm::Sha512_256 => restore::<sha2::Sha512_256>(state),
+            Algorithm::Sha3_224 => restore::<sha3::Sha3_224>(state),
+            Algorithm::Sha3_256 => restore::<sha3::Sha3_256>(state),
+            Algorithm::Sha3_384 => restore::<sha3::Sha3_384>(state),
+            Algorithm::Sha3_512 => restore::<sha3::Sha3_512>(state),
+            Algorithm::Md5 => restore::<md5::Md5>(state),
+            Algorithm::Sha1 => restore::<sha1::Sha1>(state),
+            Algorithm::Crc32c => Some(Box::new(Crc32cHasher(u32::from_be_bytes(
+                state.try_into().ok()?,
+            )))),
+            Algorithm::Blake3 | Algorithm::Xxh3_64 | Algorithm::Xxh3_128 => None,
+        }
+    }
+
+    /// Returns `true` if the state of an unkeyed hasher can be saved with
+    /// `Hasher::save_state` and restored with `restore_hasher`.
+    pub fn supports_checkpoint(self) -> bool {
+        !matches!(
+            self,
+            Algorithm::Blake3 | Algorithm::Xxh3_64 | Algorithm::Xxh3_128
+        )
+    }
+
+    /// Returns `true` if `keyed_hasher` supports this algorithm.
+    pub fn supports_key(self) -> bool {
+        matches!(
+            self,
+            Algorithm::Sha256 | Algorithm::Sha512 | Algorithm::Blake3
+        )
+    }
+
+    /// Returns the name used for this algorithm on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha224 => "sha224",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha384 => "sha384",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Sha512_256 => "sha512/256",
+            Algorithm::Sha3_224 => "sha3-224",
+            Algorithm::Sha3_256 => "sha3-256",
+            Algorithm::Sha3_384 => "sha3-384",
+            Algorithm::Sha3_512 => "sha3-512",
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Xxh3_64 => "xxh3-64",
+            Algorithm::Xxh3_128 => "xxh3-128",
+            Algorithm::Crc32c => "crc32c",
+            Algorithm::Md5 => "md5",
+            Algorithm::Sha1 => "sha1",
+        }
+    }
+
+    /// Returns the tag used for this algorithm in BSD-style `TAG (path) = hex` lines.
+    pub fn tag(self) -> &'static str {
+        match self {
+            Algorithm::Sha224 => "SHA224",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha384 => "SHA384",
+            Algorithm::Sha512 => "SHA512",
+            Algorithm::Sha512_256 => "SHA512t256",
+            Algorithm::Sha3_224 => "SHA3-224",
+            Algorithm::Sha3_256 => "SHA3-256",
+            Algorithm::Sha3_384 => "SHA3-384",
+            Algorithm::Sha3_512 => "SHA3-512",
+            Algorithm::Blake3 => "BLAKE3",
+            Algorithm::Xxh3_64 => "XXH3",
+            Algorithm::Xxh3_128 => "XXH128",
+            Algorithm::Crc32c => "CRC32C",
+            Algorithm::Md5 => "MD5",
+            Algorithm::Sha1 => "SHA1",
+        }
+    }
+
+    /// Returns the BSD-style tag for keyed digests of this algorithm.
+    pub fn keyed_tag(self) -> Option<&'static str> {
+        match self {
+            Algorithm::Sha256 => Some("HMAC-SHA256"),
+            Algorithm::Sha512 => Some("HMAC-SHA512"),
+            Algorithm::Blake3 => Some("BLAKE3-KEYED"),
+            _ => None,
+        }
+    }
+
+    /// Looks up the algorithm named by a keyed BSD-style tag.
+    pub fn from_keyed_tag(tag: &str) -> Option<Algorithm> {
+        <Algorithm as clap::ValueEnum>::value_variants()
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.keyed_tag() == Some(tag))
+    }
+
+    /// Looks up the algorithm named by a BSD-style tag.
+    pub fn from_tag(tag: &str) -> Option<Algorithm> {
+        match tag {
+            "SHA512/256" => Some(Algorithm::Sha512_256),
+            _ => <Algorithm as clap::ValueEnum>::value_variants()
+                .iter()
+                .copied()
+                .find(|algorithm| algorithm.tag() == tag),
+        }
+    }
+
+    /// Returns the length in bytes of the digests this algorithm produces.
+    pub fn digest_len(self) -> usize {
+        match self {
+            Algorithm::Sha224 | Algorithm::Sha3_224 => 28,
+            Algorithm::Sha256 | Algorithm::Sha512_256 | Algorithm::Sha3_256 | Algorithm::Blake3 => {
+                32
+            }
+            Algorithm::Sha384 | Algorithm::Sha3_384 => 48,
+            Algorithm::Sha512 | Algorithm::Sha3_512 => 64,
+            Algorithm::Xxh3_64 => 8,
+            Algorithm::Xxh3_128 | Algorithm::Md5 => 16,
+            Algorithm::Crc32c => 4,
+            Algorithm::Sha1 => 20,
+        }
+    }
+
+    /// Returns `true` for legacy algorithms with practical collision attacks.
+    ///
+    /// These are only offered for interoperability and must be opted into.
+    pub fn is_weak(self) -> bool {
+        matches!(self, Algorithm::Md5 | Algorithm::Sha1)
+    }
+
+    /// Returns `false` for fast checksums that give no integrity guarantee
+    /// against deliberate tampering.
+    pub fn is_cryptographic(self) -> bool {
+        !matches!(
+            self,
+            Algorithm::Xxh3_64 | Algorithm::Xxh3_128 | Algorithm::Crc32c
+        )
+    }
+
+    /// Renders a digest produced by this algorithm for output.
+    ///
+    /// Non-cryptographic digests carry an `<algo>:` prefix so they cannot be
+    /// mistaken for a cryptographic hash.
+    pub fn format_digest(self, digest: &[u8], encoding: Encoding) -> String {
+        if self.is_cryptographic() {
+            encoding.encode(digest)
+        } else {
+            format!("{}:{}", self.name(), encoding.encode(digest))
+        }
+    }
+
+    /// Parses a digest rendered by `format_digest` in any encoding.
+    ///
+    /// A non-cryptographic `<algo>:` prefix selects that algorithm; otherwise
+    /// the digest is read as `default`. Returns `None` if the text is not a
+    /// digest of the right length.
+    pub fn parse_digest(text: &str, default: Algorithm) -> Option<(Algorithm, Vec<u8>)> {
+        let (algorithm, encoded) = match text.split_once(':') {
+            Some((name, encoded)) => {
+                let algorithm = <Algorithm as clap::ValueEnum>::from_str(name, true).ok()?;
+                if algorithm.is_cryptographic() {
+                    return None;
+                }
+                (algorithm, encoded)
+            }
+            None => (default, text),
+        };
+        let digest = encoding::decode(encoded, algorithm.digest_len())?;
+        Some((algorithm, digest))
+    }
+}
+
+/// Options controlling how checksums are computed and printed.
+#[derive(Debug, Clone)]
+pub struct ChecksumOptions {
+    /// Hash algorithms to compute, all in a single pass over each input.
+    pub algorithms: Vec<Algorithm>,
+    /// Print BSD-style `TAG (path) = hex` lines instead of `<hex>  <path>`.
+    /// Always the case with more than one algorithm.
+    pub tag: bool,
+    /// Print one JSON record per input instead of one line per algorithm.
+    pub json: bool,
+    /// Buffers used to overlap reading and hashing.
+    pub pipeline: Pipeline,
+    /// Compute keyed digests (HMAC or keyed BLAKE3) with this key.
+    pub key: Option<SecretKey>,
+    /// Text encoding of printed digests.
+    pub encoding: Encoding,
+    /// Print one SRI `integrity` value per input, listing every algorithm.
+    pub sri: bool,
+    /// Print one line per algorithm rendered from this template.
+    pub format: Option<Template>,
+    /// Print template paths relative to this directory.
+    pub relative_to: Option<PathBuf>,
+    /// End records with NUL instead of a newline, and don't escape names.
+    pub zero: bool,
+    /// Reuse and record digests of unchanged files in this cache.
+    pub cache: Option<Arc<cache::Cache>>,
+    /// Hash files even if they are cached, and report wrong cached digests.
+    pub verify_cache: bool,
+    /// Hash only this byte range of each input, possibly in chunks.
+    pub range: Option<range::RangeOptions>,
+}
+
+impl Default for ChecksumOptions {
+    fn default() -> Self {
+        Self {
+            algorithms: vec![Algorithm::default()],
+            tag: false,
+            json: false,
+            pipeline: Pipeline::default(),
+            key: None,
+            encoding: Encoding::default(),
+            sri: false,
+            format: None,
+            relative_to: None,
+            zero: false,
+            cache: None,
+            verify_cache: false,
+            range: None,
+        }
+    }
+}
+
+/// Read-ahead buffers used by `digest_pipelined`.
+///
+/// While the hasher works through one buffer, the reader fills the next one,
+/// so disk or pipe I/O overlaps with hashing instead of taking turns with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pipeline {
+    /// Size of each buffer in bytes.
+    pub buffer_size: usize,
+    /// Number of buffers; fewer than two reads and hashes on one thread.
+    pub buffers: usize,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            buffers: DEFAULT_BUFFERS,
+        }
+    }
+}
+
+impl From<Algorithm> for ChecksumOptions {
+    fn from(algorithm: Algorithm) -> Self {
+        Self {
+            algorithms: vec![algorithm],
+            ..Self::default()
+        }
+    }
+}
+
+impl ChecksumOptions {
+    /// Returns the BSD-style tag of `algorithm`, which differs for keyed digests.
+    fn tag_of(&self, algorithm: Algorithm) -> &'static str {
+        match self.key {
+            Some(_) => algorithm.keyed_tag().unwrap_or(algorithm.tag()),
+            None => algorithm.tag(),
+        }
+    }
+
+    /// Returns the name of `algorithm` used as a JSON key and template field.
+    fn label(&self, algorithm: Algorithm) -> String {
+        match self.key {
+            Some(_) => self.tag_of(algorithm).to_ascii_lowercase(),
+            None => algorithm.name().to_string(),
+        }
+    }
+
+    /// Returns `true` if `write_record` prints the size or modification time,
+    /// so callers only look them up when needed.
+    pub fn needs_stat(&self) -> bool {
+        self.format.as_ref().is_some_and(Template::uses_stat)
+    }
+
+    /// Renders one digest in the chosen encoding, with the `<algo>:` marker of
+    /// non-cryptographic algorithms, or as an SRI hash expression.
+    fn render(&self, algorithm: Algorithm, digest: &[u8]) -> String {
+        if self.sri {
+            encoding::sri(algorithm, digest)
+        } else {
+            algorithm.format_digest(digest, self.encoding)
+        }
+    }
+
+    /// Writes the output for one input, given one digest per algorithm.
+    ///
+    /// This is a `<hex>  <path>` line, or a BSD-style line per algorithm with
+    /// `tag` or several algorithms, or a single JSON object with `json`. With
+    /// `sri`, it is a single `<integrity>  <path>` line instead, and with
+    /// `format` one templated line per algorithm, using `stat`.
+    ///
+    /// Lines for names that need escaping start with `\`, see `escape`.
+    /// With `zero`, records end with NUL and names are written raw.
+    pub fn write_record<W: Write>(
+        &self,
+        mut writer: W,
+        digests: &[Vec<u8>],
+        path_display: &str,
+        stat: FileStat,
+    ) -> io::Result<()> {
+        let entries = self.algorithms.iter().zip(digests);
+        let end = if self.zero { '\0' } else { '\n' };
+        let (prefix, escaped_path) = match escape::escape_filename(path_display) {
+            Some(escaped) if !self.zero => ("\\", escaped),
+            _ => ("", path_display.to_string()),
+        };
+        if let Some(template) = &self.format {
+            let path = match &self.relative_to {
+                Some(base) if path_display != "-" => {
+                    template::relative_path(Path::new(path_display), base)?
+                        .display()
+                        .to_string()
+                }
+                _ => path_display.to_string(),
+            };
+            for (algorithm, digest) in entries {
+                let line = template.render(&template::Values {
+                    algo: &self.label(*algorithm),
+                    hash: &algorithm.format_digest(digest, self.encoding),
+                    stat,
+                    path: &path,
+                });
+                write!(writer, "{}{}", line, end)?;
+            }
+            Ok(())
+        } else if self.json {
+            write!(
+                writer,
+                "{{\"path\":{}",
+                serde_json::to_string(path_display)?
+            )?;
+            for (algorithm, digest) in entries {
+                write!(
+                    writer,
+                    ",{}:{}",
+                    serde_json::to_string(&self.label(*algorithm))?,
+                    serde_json::to_string(&self.render(*algorithm, digest))?
+                )?;
+            }
+            writeln!(writer, "}}")
+        } else if self.sri {
+            let integrity: Vec<_> = entries
+                .map(|(algorithm, digest)| self.render(*algorithm, digest))
+                .collect();
+            write!(
+                writer,
+                "{}{}  {}{}",
+                prefix,
+                integrity.join(" "),
+                escaped_path,
+                end
+            )
+        } else if self.tag || self.algorithms.len() > 1 {
+            for (algorithm, digest) in entries {
+                write!(
+                    writer,
+                    "{}{} ({}) = {}{}",
+                    prefix,
+                    self.tag_of(*algorithm),
+                    escaped_path,
+                    self.encoding.encode(digest),
+                    end
+                )?;
+            }
+            Ok(())
+        } else {
+            for (algorithm, digest) in entries {
+                write!(
+                    writer,
+                    "{}{}  {}{}",
+                    prefix,
+                    algorithm.format_digest(digest, self.encoding),
+                    escaped_path,
+                    end
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Incremental hash state fed by `MultiHasher`.
+pub trait Hasher: Send {
+    /// Feeds `data` into the hash state.
+    fn update(&mut self, data: &[u8]);
+
+    /// Feeds a large in-memory `data` into the hash state, using all cores
+    /// if the algorithm can.
+    fn update_parallel(&mut self, data: &[u8]) {
+        self.update(data);
+    }
+
+    /// Consumes the hasher and returns the raw digest bytes.
+    fn finalize(self: Box<Self>) -> Vec<u8>;
+
+    /// Returns the internal state for `Algorithm::restore_hasher`, or `None`
+    /// if it can't be saved.
+    fn save_state(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// One `Hasher` per requested algorithm, all fed from the same buffers so
+/// that an input is only read once.
+pub struct MultiHasher(Vec<Box<dyn Hasher>>);
+
+impl MultiHasher {
+    /// Creates fresh hashers for `algorithms`, keyed with `key` if given.
+    pub fn new(algorithms: &[Algorithm], key: Option<&SecretKey>) -> io::Result<Self> {
+        algorithms
+            .iter()
+            .map(|algorithm| match key {
+                Some(key) => algorithm.keyed_hasher(key),
+                None => Ok(algorithm.hasher()),
+            })
+            .collect::<io::Result<_>>()
+            .map(Self)
+    }
+
+    /// Feeds `data` into every hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        for hasher in &mut self.0 {
+            hasher.update(data);
+        }
+    }
+
+    /// Feeds a large in-memory `data` into every hasher, see
+    /// `Hasher::update_parallel`.
+    pub fn update_parallel(&mut self, data: &[u8]) {
+        for hasher in &mut self.0 {
+            hasher.update_parallel(data);
+        }
+    }
+
+    /// Returns the raw digests, in the order the algorithms were given.
+    pub fn finalize(self) -> Vec<Vec<u8>> {
+        self.0.into_iter().map(|hasher| hasher.finalize()).collect()
+    }
+
+    /// Returns the state of every hasher, or `None` if one can't be saved.
+    pub fn save_state(&self) -> Option<Vec<Vec<u8>>> {
+        self.0.iter().map(|hasher| hasher.save_state()).collect()
+    }
+
+    /// Recreates unkeyed hashers for `algorithms` from `save_state`.
+    pub fn restore(algorithms: &[Algorithm], states: &[Vec<u8>]) -> Option<Self> {
+        if algorithms.len() != states.len() {
+            return None;
+        }
+        algorithms
+            .iter()
+            .zip(states)
+            .map(|(algorithm, state)| algorithm.restore_hasher(state))
+            .collect::<Option<_>>()
+            .map(Self)
+    }
+}
+
+/// Adapts any RustCrypto `Digest` to the `Hasher` trait.
+struct DigestHasher<D>(D);
+
+impl<D: Digest + SerializableState + Send> Hasher for DigestHasher<D> {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(&mut self.0, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        Some(self.0.serialize().to_vec())
+    }
+}
+
+/// Adapts an HMAC instance to the `Hasher` trait.
+struct HmacHasher<M>(M);
+
+impl<M: hmac::Mac + Send> Hasher for HmacHasher<M> {
+    fn update(&mut self, data: &[u8]) {
+        hmac::Mac::update(&mut self.0, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().into_bytes().to_vec()
+    }
+}
+
+/// Adapts `blake3::Hasher` to the `Hasher` trait.
+struct Blake3Hasher(blake3::Hasher);
+
+impl Hasher for Blake3Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn update_parallel(&mut self, data: &[u8]) {
+        self.0.update_rayon(data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().as_bytes().to_vec()
+    }
+}
+
+/// Adapts `xxhash_rust::xxh3::Xxh3` to the `Hasher` trait with a 64-bit output.
+struct Xxh3_64Hasher(xxhash_rust::xxh3::Xxh3);
+
+impl Hasher for Xxh3_64Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.digest().to_be_bytes().to_vec()
+    }
+}
+
+/// Adapts `xxhash_rust::xxh3::Xxh3` to the `Hasher` trait with a 128-bit output.
+struct Xxh3_128Hasher(xxhash_rust::xxh3::Xxh3);
+
+impl Hasher for Xxh3_128Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.digest128().to_be_bytes().to_vec()
+    }
+}
+
+/// Running CRC-32C value exposed through the `Hasher` trait.
+struct Crc32cHasher(u32);
+
+impl Hasher for Crc32cHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0 = crc32c::crc32c_append(self.0, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        Some(self.0.to_be_bytes().to_vec())
+    }
+}
 
 /// Argument handler for the checksum command.
 #[derive(Debug, Default)]
@@ -33,45 +734,944 @@ impl CommandArg for ChecksumArgument {
             .value_parser(clap::value_parser!(PathBuf))
     }
 
+    fn options(&self) -> Vec<clap::Arg> {
+        #[allow(unused_mut)]
+        let mut options = vec![
+            clap::Arg::new("algo")
+                .long("algo")
+                .help("Hash algorithms used by --checksum, comma-separated")
+                .value_parser(clap::value_parser!(Algorithm))
+                .value_delimiter(',')
+                .action(clap::ArgAction::Append)
+                .default_value("sha256"),
+            clap::Arg::new("allow-weak-hashes")
+                .long("allow-weak-hashes")
+                .help("Allow the broken md5 and sha1 algorithms for interoperability")
+                .action(clap::ArgAction::SetTrue),
+            clap::Arg::new("tag")
+                .long("tag")
+                .help("Print BSD-style checksum lines")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("check"),
+            clap::Arg::new("json")
+                .long("json")
+                .help("Print one JSON record with every digest per file")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["check", "tag"]),
+            clap::Arg::new("encoding")
+                .long("encoding")
+                .help("Text encoding of printed digests")
+                .value_parser(clap::value_parser!(Encoding))
+                .default_value("hex")
+                .conflicts_with("check"),
+            clap::Arg::new("sri")
+                .long("sri")
+                .help("Print Subresource Integrity values (sha256, sha384 or sha512)")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["check", "tag", "encoding", "key-file", "key-env"]),
+            clap::Arg::new("format")
+                .long("format")
+                .help("Template for each line, e.g. '{algo}:{hash} {size} {path}'; fields: algo, hash, size, mtime, path")
+                .value_parser(clap::value_parser!(Template))
+                .conflicts_with_all(["check", "tag", "json", "sri"]),
+            clap::Arg::new("relative-to")
+                .long("relative-to")
+                .help("Print {path} in --format relative to this directory")
+                .value_name("DIR")
+                .value_parser(clap::value_parser!(PathBuf))
+                .requires("format"),
+            clap::Arg::new("zero")
+                .short('z')
+                .long("zero")
+                .help("End each output line with NUL, not newline, and don't escape file names")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("json"),
+            clap::Arg::new("cache")
+                .long("cache")
+                .help("Reuse digests of unchanged files from a cache file (default in the XDG cache dir)")
+                .value_name("PATH")
+                .num_args(0..=1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all(["check", "tree", "key-file", "key-env"]),
+            clap::Arg::new("no-cache")
+                .long("no-cache")
+                .help("Don't read or update the checksum cache")
+                .action(clap::ArgAction::SetTrue)
+                .overrides_with("cache"),
+            clap::Arg::new("verify-cache")
+                .long("verify-cache")
+                .help("Hash cached files anyway and fail if a cached digest was wrong")
+                .action(clap::ArgAction::SetTrue)
+                .requires("cache"),
+            clap::Arg::new("dupes")
+                .long("dupes")
+                .help("Print sets of files with identical contents instead of checksums")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["check", "tree", "tag", "sri", "format", "zero"]),
+            clap::Arg::new("link")
+                .long("link")
+                .help("Replace duplicates found by --dupes with links to the first file")
+                .value_parser(clap::value_parser!(dupes::LinkMode))
+                .requires("dupes"),
+            clap::Arg::new("offset")
+                .long("offset")
+                .help("Skip this many bytes of each input before hashing")
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u64))
+                .conflicts_with_all(["check", "tree", "dupes", "manifest-diff", "cache"]),
+            clap::Arg::new("length")
+                .long("length")
+                .help("Hash at most this many bytes of each input")
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u64))
+                .conflicts_with_all(["check", "tree", "dupes", "manifest-diff", "cache"]),
+            clap::Arg::new("chunk-size")
+                .long("chunk-size")
+                .help("Print one digest per block of this many bytes")
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .conflicts_with_all(["check", "tree", "dupes", "manifest-diff", "cache"]),
+            clap::Arg::new("chunks")
+                .long("chunks")
+                .help("Print the SHA-256 of each content-defined chunk and a deduplication summary")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "check",
+                    "tree",
+                    "dupes",
+                    "manifest-diff",
+                    "cache",
+                    "algo",
+                    "offset",
+                    "length",
+                    "chunk-size",
+                    "key-file",
+                    "key-env",
+                ]),
+            clap::Arg::new("avg-chunk-size")
+                .long("avg-chunk-size")
+                .help("Average size in bytes of the chunks found by --chunks")
+                .value_name("BYTES")
+                .value_parser(
+                    clap::value_parser!(u32)
+                        .range(i64::from(cdc::MIN_AVG_SIZE)..=i64::from(cdc::MAX_AVG_SIZE)),
+                )
+                .default_value("65536")
+                .requires("chunks"),
+            clap::Arg::new("checkpoint")
+                .long("checkpoint")
+                .help("Periodically save the hash state of a single input to this file")
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all([
+                    "check",
+                    "tree",
+                    "dupes",
+                    "manifest-diff",
+                    "cache",
+                    "recursive",
+                    "offset",
+                    "length",
+                    "chunk-size",
+                    "chunks",
+                    "key-file",
+                    "key-env",
+                ]),
+            clap::Arg::new("checkpoint-interval")
+                .long("checkpoint-interval")
+                .help("Bytes hashed between two saves of the checkpoint")
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("1073741824")
+                .requires("checkpoint"),
+            clap::Arg::new("resume")
+                .long("resume")
+                .help("Continue hashing from the state saved by --checkpoint")
+                .action(clap::ArgAction::SetTrue)
+                .requires("checkpoint"),
+            clap::Arg::new("recursive")
+                .short('r')
+                .long("recursive")
+                .help("Hash every regular file below directory arguments")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("check"),
+            clap::Arg::new("tree")
+                .long("tree")
+                .help("Print a single digest covering each directory tree")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["check", "recursive"]),
+            clap::Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .help("Number of files to hash concurrently (0 uses every core)")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                // Modes that read their inputs one at a time
+                .conflicts_with_all([
+                    "check",
+                    "tree",
+                    "dupes",
+                    "chunks",
+                    "manifest-diff",
+                    "checkpoint",
+                ]),
+            clap::Arg::new("buffer-size")
+                .long("buffer-size")
+                .help("Size in bytes of each read-ahead buffer")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                .default_value("262144"),
+            clap::Arg::new("buffers")
+                .long("buffers")
+                .help("Number of read-ahead buffers (1 reads and hashes on one thread)")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                .default_value("4"),
+            clap::Arg::new("symlinks")
+                .long("symlinks")
+                .help("How --recursive and --tree treat symbolic links")
+                .value_parser(clap::value_parser!(SymlinkMode))
+                .default_value("follow"),
+            clap::Arg::new("one-file-system")
+                .long("one-file-system")
+                .help("Don't descend into directories on other filesystems")
+                .action(clap::ArgAction::SetTrue),
+            clap::Arg::new("check")
+                .long("check")
+                .help("Read checksums from the given manifests and verify them")
+                .num_args(0..)
+                .value_parser(clap::value_parser!(PathBuf)),
+            clap::Arg::new("manifest-diff")
+                .long("manifest-diff")
+                .help("Compare two manifests and print added, removed, modified and renamed files")
+                .value_names(["OLD", "NEW"])
+                .num_args(0..=2)
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all([
+                    "check",
+                    "tree",
+                    "dupes",
+                    "tag",
+                    "sri",
+                    "format",
+                    "cache",
+                    "recursive",
+                ]),
+            clap::Arg::new("quiet")
+                .long("quiet")
+                .help("Don't print OK for each successfully verified file")
+                .action(clap::ArgAction::SetTrue)
+                .requires("check"),
+            clap::Arg::new("status")
+                .long("status")
+                .help("Don't output anything, the exit status shows success")
+                .action(clap::ArgAction::SetTrue)
+                .requires("check"),
+            clap::Arg::new("warn")
+                .short('w')
+                .long("warn")
+                .help("Warn about improperly formatted checksum lines")
+                .action(clap::ArgAction::SetTrue)
+                .requires("check"),
+            clap::Arg::new("ignore-missing")
+                .long("ignore-missing")
+                .help("Don't fail or report status for missing files")
+                .action(clap::ArgAction::SetTrue)
+                .requires("check"),
+            clap::Arg::new("signed-by")
+                .long("signed-by")
+                .help("Check each manifest against <manifest>.sig with this public key first")
+                .value_name("PUBLIC_KEY")
+                .value_parser(clap::value_parser!(PathBuf))
+                .requires("check"),
+            clap::Arg::new("key-file")
+                .long("key-file")
+                .help("Compute HMAC or keyed BLAKE3 digests with the key in this file")
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with("key-env"),
+            clap::Arg::new("key-env")
+                .long("key-env")
+                .help("Compute HMAC or keyed BLAKE3 digests with the key in this environment variable")
+                .value_name("NAME"),
+        ];
+        #[cfg(unix)]
+        options.push(
+            clap::Arg::new("key-fd")
+                .long("key-fd")
+                .help("Compute HMAC or keyed BLAKE3 digests with the key read from this file descriptor")
+                .value_parser(clap::value_parser!(i32))
+                .conflicts_with_all(["key-file", "key-env", "sri", "cache", "chunks", "checkpoint"]),
+        );
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        options.push(
+            clap::Arg::new("io-uring")
+                .long("io-uring")
+                .help("Batch file reads through io_uring, falling back if it is unavailable")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "check",
+                    "tree",
+                    "jobs",
+                    "cache",
+                    "dupes",
+                    "manifest-diff",
+                    "offset",
+                    "length",
+                    "chunk-size",
+                    "checkpoint",
+                    "chunks",
+                ]),
+        );
+        options
+    }
+
     fn run(&self, matches: &clap::ArgMatches, writer: &mut dyn std::io::Write) -> Result<()> {
         if matches.contains_id(self.name()) {
             let files = matches
                 .get_many::<PathBuf>(self.name())
                 .map(|v| v.cloned().collect::<Vec<_>>())
                 .unwrap_or_default();
+            let mut algorithms = Vec::new();
+            for algorithm in matches.get_many::<Algorithm>("algo").into_iter().flatten() {
+                if !algorithms.contains(algorithm) {
+                    algorithms.push(*algorithm);
+                }
+            }
+            if algorithms.is_empty() {
+                algorithms.push(Algorithm::default());
+            }
+
+            let key = match key_for(matches, &algorithms) {
+                // Like `diff`, any failure to compare manifests exits with 2
+                Err(error) if matches.contains_id("manifest-diff") => {
+                    return Err(Trouble(error).into());
+                }
+                key => key?,
+            };
+
+            let sri = matches.get_flag("sri");
+            let input_options = InputOptions {
+                recursive: matches.get_flag("recursive"),
+                symlinks: matches
+                    .get_one::<SymlinkMode>("symlinks")
+                    .copied()
+                    .unwrap_or_default(),
+                one_file_system: matches.get_flag("one-file-system"),
+            };
+            let verifying_key = matches
+                .get_one::<PathBuf>("signed-by")
+                .map(|path| signature::read_verifying_key(path))
+                .transpose()?;
+            if matches.contains_id("check") {
+                let [algorithm] = algorithms[..] else {
+                    anyhow::bail!("--check takes a single --algo for untagged lines");
+                };
+                return self.run_check(matches, files, algorithm, key, verifying_key, writer);
+            }
+            if matches.contains_id("manifest-diff") {
+                let [algorithm] = algorithms[..] else {
+                    return Err(Trouble(anyhow::anyhow!(
+                        "--manifest-diff takes a single --algo for untagged lines"
+                    ))
+                    .into());
+                };
+                return self.run_diff(matches, files, algorithm, key.is_some(), writer);
+            }
+
+            let options = ChecksumOptions {
+                algorithms,
+                tag: matches.get_flag("tag"),
+                json: matches.get_flag("json"),
+                pipeline: pipeline_from(matches),
+                key,
+                encoding: matches
+                    .get_one::<Encoding>("encoding")
+                    .copied()
+                    .unwrap_or_default(),
+                sri,
+                format: matches.get_one::<Template>("format").cloned(),
+                relative_to: matches.get_one::<PathBuf>("relative-to").cloned(),
+                zero: matches.get_flag("zero"),
+                cache: cache_from(matches)?,
+                verify_cache: matches.get_flag("verify-cache"),
+                range: range_from(matches),
+            };
+            if let Some(path) = matches.get_one::<PathBuf>("checkpoint") {
+                let checkpoint = checkpoint::CheckpointOptions {
+                    path: path.clone(),
+                    interval: *matches.get_one::<u64>("checkpoint-interval").unwrap(),
+                    resume: matches.get_flag("resume"),
+                };
+                return checkpoint::process_checkpointed(&files, writer, &options, &checkpoint);
+            }
+            if matches.get_flag("tree") {
+                return self.run_tree(&files, &options, &input_options, writer);
+            }
+            if matches.get_flag("chunks") {
+                let avg_size = *matches.get_one::<u32>("avg-chunk-size").unwrap();
+                return cdc::process_chunks(&files, &input_options, writer, &options, avg_size);
+            }
+            if matches.get_flag("dupes") {
+                let link = matches.get_one::<dupes::LinkMode>("link").copied();
+                let result = dupes::process_dupes(&files, &input_options, writer, &options, link);
+                if let Some(cache) = &options.cache {
+                    cache.save()?;
+                }
+                return result;
+            }
+
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            if matches.get_flag("io-uring")
+                && !files.is_empty()
+                && let Some(ring) = uring::ring()
+            {
+                return uring::process_checksum_batched(
+                    ring,
+                    &files,
+                    &input_options,
+                    writer,
+                    &options,
+                );
+            }
+
+            let jobs = match matches.get_one::<usize>("jobs").copied().unwrap_or(1) {
+                0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+                jobs => jobs,
+            };
+            let result = process_inputs_parallel(
+                &files,
+                &input_options,
+                jobs,
+                writer,
+                |input, path_display, writer| {
+                    process_checksum_input(input, path_display, writer, &options)
+                },
+            );
+            // Keep what was hashed before a failure
+            if let Some(cache) = &options.cache {
+                cache.save()?;
+            }
+            result?;
+            if let Some(cache) = &options.cache
+                && cache.mismatches() > 0
+            {
+                anyhow::bail!(
+                    "{} cached digests were wrong; the cache has been corrected",
+                    cache.mismatches()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ChecksumArgument {
+    /// Verifies the manifests given to `--check` (or as files if it has no values).
+    fn run_check(
+        &self,
+        matches: &clap::ArgMatches,
+        files: Vec<PathBuf>,
+        algorithm: Algorithm,
+        key: Option<SecretKey>,
+        verifying_key: Option<ed25519_dalek::VerifyingKey>,
+        writer: &mut dyn std::io::Write,
+    ) -> Result<()> {
+        let manifests = matches
+            .get_many::<PathBuf>("check")
+            .map(|v| v.cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let manifests = if manifests.is_empty() {
+            files
+        } else {
+            manifests
+        };
+        let options = check::CheckOptions {
+            algorithm,
+            quiet: matches.get_flag("quiet"),
+            status: matches.get_flag("status"),
+            warn: matches.get_flag("warn"),
+            ignore_missing: matches.get_flag("ignore-missing"),
+            allow_weak: matches.get_flag("allow-weak-hashes"),
+            pipeline: pipeline_from(matches),
+            key,
+            zero: matches.get_flag("zero"),
+        };
 
-            process_inputs(&files, writer, |mut reader, path_display, writer| {
-                process_checksum_internal(&mut reader, path_display, writer)
+        let failed = Cell::new(false);
+        if let Some(verifying_key) = verifying_key {
+            if manifests.is_empty() {
+                anyhow::bail!("--signed-by needs manifest files, not stdin");
+            }
+            for manifest in &manifests {
+                let path_display = manifest.display().to_string();
+                // Check exactly the bytes whose signature was verified
+                let Some(contents) = signature::verify(manifest, &verifying_key)? else {
+                    failed.set(true);
+                    if !options.status {
+                        writeln!(writer, "{}: signature FAILED", path_display)?;
+                    }
+                    continue;
+                };
+                let summary = check::process_check_internal(
+                    contents.as_slice(),
+                    &path_display,
+                    &mut *writer,
+                    &options,
+                )?;
+                if summary.is_failure(&options) {
+                    failed.set(true);
+                }
+            }
+        } else {
+            process_inputs(&manifests, writer, |input, path_display, writer| {
+                let summary = check::process_check_internal(input, path_display, writer, &options)?;
+                if summary.is_failure(&options) {
+                    failed.set(true);
+                }
+                Ok(())
             })?;
         }
+        if failed.get() {
+            return Err(SilentFailure.into());
+        }
+        Ok(())
+    }
+
+    /// Compares the manifests given to `--manifest-diff` (or as files if it
+    /// has no values).
+    fn run_diff(
+        &self,
+        matches: &clap::ArgMatches,
+        files: Vec<PathBuf>,
+        algorithm: Algorithm,
+        keyed: bool,
+        writer: &mut dyn std::io::Write,
+    ) -> Result<()> {
+        let manifests = matches
+            .get_many::<PathBuf>("manifest-diff")
+            .map(|v| v.cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let manifests = if manifests.is_empty() {
+            files
+        } else {
+            manifests
+        };
+        let [old, new] = &manifests[..] else {
+            return Err(Trouble(anyhow::anyhow!(
+                "--manifest-diff takes an old and a new manifest"
+            ))
+            .into());
+        };
+        let options = diff::DiffOptions {
+            algorithm,
+            keyed,
+            json: matches.get_flag("json"),
+            zero: matches.get_flag("zero"),
+        };
+        diff::process_diff(old, new, writer, &options)
+    }
+
+    /// Prints the tree digests of each argument for `--tree`.
+    fn run_tree(
+        &self,
+        files: &[PathBuf],
+        options: &ChecksumOptions,
+        input_options: &InputOptions,
+        writer: &mut dyn std::io::Write,
+    ) -> Result<()> {
+        if files.is_empty() {
+            anyhow::bail!("--tree needs at least one directory argument");
+        }
+        for path in files {
+            let digests = tree::digest_tree(
+                path,
+                &options.algorithms,
+                options.key.as_ref(),
+                options.pipeline,
+                input_options,
+            )?;
+            let stat = if options.needs_stat() {
+                FileStat::from(&std::fs::metadata(path)?)
+            } else {
+                FileStat::default()
+            };
+            options.write_record(&mut *writer, &digests, &path.display().to_string(), stat)?;
+        }
         Ok(())
     }
 }
 
-/// Computes the SHA256 checksum of the input and prints it.
+/// Reads `--offset`, `--length` and `--chunk-size`, if any of them is given.
+fn range_from(matches: &clap::ArgMatches) -> Option<range::RangeOptions> {
+    let offset = matches.get_one::<u64>("offset").copied();
+    let length = matches.get_one::<u64>("length").copied();
+    let chunk_size = matches.get_one::<u64>("chunk-size").copied();
+    if offset.is_none() && length.is_none() && chunk_size.is_none() {
+        return None;
+    }
+    Some(range::RangeOptions {
+        offset: offset.unwrap_or(0),
+        length,
+        chunk_size,
+    })
+}
+
+/// Reads the read-ahead buffer options.
+///
+/// Unless `--buffers` is given, a single-core machine reads and hashes on one
+/// thread, since the two can't overlap there.
+fn pipeline_from(matches: &clap::ArgMatches) -> Pipeline {
+    let default = Pipeline::default();
+    let single_core = std::thread::available_parallelism().is_ok_and(|n| n.get() == 1);
+    let buffers = match matches.value_source("buffers") {
+        Some(clap::parser::ValueSource::DefaultValue) if single_core => 1,
+        _ => matches
+            .get_one::<usize>("buffers")
+            .copied()
+            .unwrap_or(default.buffers),
+    };
+    Pipeline {
+        buffer_size: matches
+            .get_one::<usize>("buffer-size")
+            .copied()
+            .unwrap_or(default.buffer_size),
+        buffers,
+    }
+}
+
+/// Opens the cache given by `--cache`, unless overridden by `--no-cache`.
+fn cache_from(matches: &clap::ArgMatches) -> Result<Option<Arc<cache::Cache>>> {
+    if !matches.contains_id("cache") || matches.get_flag("no-cache") {
+        return Ok(None);
+    }
+    let path = match matches.get_one::<PathBuf>("cache") {
+        Some(path) => path.clone(),
+        None => cache::default_path().ok_or_else(|| {
+            anyhow::anyhow!("No cache directory found; set XDG_CACHE_HOME or pass --cache PATH")
+        })?,
+    };
+    Ok(Some(Arc::new(cache::Cache::open(&path)?)))
+}
+
+/// Checks that `algorithms` can be used with the flags in `matches`, warning
+/// about weak ones, and reads the key to use with them, if any.
+fn key_for(matches: &clap::ArgMatches, algorithms: &[Algorithm]) -> Result<Option<SecretKey>> {
+    for algorithm in algorithms.iter().filter(|algorithm| algorithm.is_weak()) {
+        if !matches.get_flag("allow-weak-hashes") {
+            anyhow::bail!(
+                "{} is cryptographically broken; pass --allow-weak-hashes to use it anyway",
+                algorithm.name()
+            );
+        }
+        eprintln!(
+            "WARNING: {} is cryptographically broken and does not protect against tampering",
+            algorithm.name()
+        );
+    }
+
+    let key = key_from(matches)?;
+    if let Some(key) = &key {
+        for algorithm in algorithms {
+            if !algorithm.supports_key() {
+                anyhow::bail!(
+                    "{} cannot be used with a key; use sha256, sha512 or blake3",
+                    algorithm.name()
+                );
+            }
+            if *algorithm == Algorithm::Blake3 {
+                key.blake3_key()?;
+            }
+        }
+    }
+
+    if matches.get_flag("sri")
+        && let Some(algorithm) = algorithms
+            .iter()
+            .find(|algorithm| encoding::sri_name(**algorithm).is_none())
+    {
+        anyhow::bail!(
+            "{} cannot be used with --sri; use sha256, sha384 or sha512",
+            algorithm.name()
+        );
+    }
+    Ok(key)
+}
+
+/// Reads the key given by `--key-file`, `--key-fd` or `--key-env`, if any.
+fn key_from(matches: &clap::ArgMatches) -> Result<Option<SecretKey>> {
+    if let Some(path) = matches.get_one::<PathBuf>("key-file") {
+        return SecretKey::from_file(path).map(Some);
+    }
+    #[cfg(unix)]
+    if let Some(fd) = matches.get_one::<i32>("key-fd") {
+        return SecretKey::from_fd(*fd).map(Some);
+    }
+    if let Some(name) = matches.get_one::<String>("key-env") {
+        return SecretKey::from_env(name).map(Some);
+    }
+    Ok(None)
+}
+
+/// Computes the checksum of the input and prints it.
 ///
 /// # Arguments
 ///
 /// * `reader` - Input reader.
 /// * `path_display` - Display string for the input path (or "-" for stdin).
 /// * `writer` - Output writer.
+/// * `options` - Algorithm and output format.
 pub fn process_checksum_internal<R: Read, W: Write>(
-    mut reader: R,
+    reader: R,
+    path_display: &str,
+    writer: W,
+    options: &ChecksumOptions,
+) -> Result<()> {
+    let digests = digest_pipelined(
+        reader,
+        &options.algorithms,
+        options.key.as_ref(),
+        options.pipeline,
+    )?;
+    options.write_record(writer, &digests, path_display, FileStat::default())?;
+    Ok(())
+}
+
+/// Computes the checksum of an input yielded by `process_inputs` and prints it.
+///
+/// See `digest_input` for how the input is read.
+///
+/// # Arguments
+///
+/// * `input` - Input stream.
+/// * `path_display` - Display string for the input path (or "-" for stdin).
+/// * `writer` - Output writer.
+/// * `options` - Algorithm and output format.
+pub fn process_checksum_input<W: Write>(
+    mut input: Input,
     path_display: &str,
-    mut writer: W,
+    writer: W,
+    options: &ChecksumOptions,
 ) -> Result<()> {
+    let metadata = match &input {
+        Input::File { reader, .. } if options.cache.is_some() || options.needs_stat() => {
+            Some(reader.get_ref().metadata()?)
+        }
+        _ => None,
+    };
+    let stat = match (&input, &metadata) {
+        _ if !options.needs_stat() => FileStat::default(),
+        (_, Some(metadata)) => FileStat::from(metadata),
+        (Input::SymlinkTarget(target), _) => FileStat {
+            size: Some(target.get_ref().len() as u64),
+            mtime: None,
+        },
+        _ => FileStat::default(),
+    };
+    if let Some(range) = options.range {
+        return range::process_range_input(&mut input, path_display, writer, options, range, stat);
+    }
+    let digests = digest_cached(&mut input, metadata.as_ref(), path_display, options)?;
+    options.write_record(writer, &digests, path_display, stat)?;
+    Ok(())
+}
+
+/// Returns the digests of an input yielded by `process_inputs`, going
+/// through `options.cache` for regular files.
+///
+/// `metadata` is that of the open file before it is read; without it, the
+/// cache is not used.
+pub fn digest_cached(
+    input: &mut Input,
+    metadata: Option<&std::fs::Metadata>,
+    path_display: &str,
+    options: &ChecksumOptions,
+) -> Result<Vec<Vec<u8>>> {
+    let cached = match (&options.cache, metadata) {
+        (Some(cache), Some(metadata)) => cache.lookup(metadata, &options.algorithms),
+        _ => None,
+    };
+    if let Some(digests) = cached.clone()
+        && !options.verify_cache
+    {
+        return Ok(digests);
+    }
+
+    let digests = digest_input(
+        input,
+        &options.algorithms,
+        options.key.as_ref(),
+        options.pipeline,
+    )?;
+    if let (Some(cache), Some(before), Input::File { reader, .. }) =
+        (&options.cache, metadata, &*input)
+    {
+        if cached.is_some_and(|cached| cached != digests) {
+            eprintln!(
+                "WARNING: {}: cached digest was wrong; updating the cache",
+                path_display
+            );
+            cache.record_mismatch();
+        }
+        let after = reader.get_ref().metadata()?;
+        cache.store(before, &after, &options.algorithms, &digests);
+    }
+    Ok(digests)
+}
+
+/// Returns the raw digest of everything `reader` yields for each algorithm,
+/// keyed with `key` if given.
+pub fn digest_reader<R: Read>(
+    mut reader: R,
+    algorithms: &[Algorithm],
+    key: Option<&SecretKey>,
+) -> io::Result<Vec<Vec<u8>>> {
     let mut buffer = HashWriter {
-        hasher: Sha256::new(),
+        hasher: MultiHasher::new(algorithms, key)?,
     };
     io::copy(&mut reader, &mut buffer)?;
-    let result = buffer.hasher.finalize();
-    let checksum = hex::encode(result);
-    writeln!(writer, "{}  {}", &checksum, path_display)?;
-    Ok(())
+    Ok(buffer.hasher.finalize())
+}
+
+/// Returns the raw digest of everything `reader` yields for each algorithm,
+/// keyed with `key` if given, reading ahead into the buffers described by
+/// `pipeline`.
+///
+/// The first buffer is filled on the calling thread; if the input ends
+/// there, it is hashed right away. Otherwise a hashing thread is started and
+/// the calling thread keeps refilling whichever buffer the hasher has
+/// returned. Reading stays on the calling thread because readers such as
+/// `StdinLock` cannot be sent to another one.
+pub fn digest_pipelined<R: Read>(
+    mut reader: R,
+    algorithms: &[Algorithm],
+    key: Option<&SecretKey>,
+    pipeline: Pipeline,
+) -> io::Result<Vec<Vec<u8>>> {
+    let buffer_size = pipeline.buffer_size.max(1);
+    let mut hasher = MultiHasher::new(algorithms, key)?;
+    let mut first = vec![0; buffer_size];
+    let len = fill(&mut reader, &mut first)?;
+    hasher.update(&first[..len]);
+    if len < first.len() {
+        return Ok(hasher.finalize());
+    }
+    if pipeline.buffers < 2 {
+        loop {
+            match read_retrying(&mut reader, &mut first)? {
+                0 => return Ok(hasher.finalize()),
+                len => hasher.update(&first[..len]),
+            }
+        }
+    }
+
+    let (full_sender, full) = mpsc::sync_channel::<(Vec<u8>, usize)>(pipeline.buffers);
+    let (empty_sender, empty) = mpsc::channel();
+    empty_sender.send(first).unwrap();
+    for _ in 1..pipeline.buffers {
+        empty_sender.send(vec![0; buffer_size]).unwrap();
+    }
+
+    thread::scope(|scope| {
+        let hashing = scope.spawn(move || {
+            for (buffer, len) in full {
+                hasher.update(&buffer[..len]);
+                // The reader stops taking buffers back once it is done.
+                let _ = empty_sender.send(buffer);
+            }
+            hasher.finalize()
+        });
+
+        let read = (|| {
+            // Only fails if the hashing thread panicked, which join reports.
+            while let Ok(mut buffer) = empty.recv() {
+                let len = read_retrying(&mut reader, &mut buffer)?;
+                if len == 0 || full_sender.send((buffer, len)).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })();
+        drop(full_sender);
+        let digests = hashing.join().expect("hashing thread panicked");
+        read.map(|()| digests)
+    })
+}
+
+/// Reads into `buffer` until it is full or the input ends.
+fn fill<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match read_retrying(reader, &mut buffer[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+    Ok(len)
+}
+
+/// Like `Read::read`, but retries reads interrupted by a signal.
+fn read_retrying<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buffer) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Returns the raw digest of an input yielded by `process_inputs` for each
+/// algorithm, keyed with `key` if given.
+///
+/// Regular files of at least `MMAP_THRESHOLD` bytes are hashed through a
+/// memory map (see `digest_mapped`); stdin, pipes, special files and small
+/// files are streamed through `digest_pipelined`.
+pub fn digest_input(
+    input: &mut Input,
+    algorithms: &[Algorithm],
+    key: Option<&SecretKey>,
+    pipeline: Pipeline,
+) -> io::Result<Vec<Vec<u8>>> {
+    if let Input::File { reader, .. } = input
+        && let Some(digests) = digest_mapped(reader.get_ref(), algorithms, key)?
+    {
+        return Ok(digests);
+    }
+    digest_pipelined(input, algorithms, key, pipeline)
+}
+
+/// Hashes a large regular file through a read-only memory map.
+///
+/// Returns `None` without reading from `file` if it is not a regular file, is
+/// smaller than `MMAP_THRESHOLD` or cannot be mapped, and also if it shrinks
+/// while it is hashed; the caller then streams it instead. The file size is
+/// re-checked before every `MMAP_CHUNK_SIZE` bytes, which leaves only a short
+/// window in which a concurrent truncation can still fault the process.
+pub fn digest_mapped(
+    file: &File,
+    algorithms: &[Algorithm],
+    key: Option<&SecretKey>,
+) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let metadata = file.metadata()?;
+    let len = metadata.len();
+    if !metadata.is_file() || len < MMAP_THRESHOLD {
+        return Ok(None);
+    }
+    // SAFETY: the map is read-only and only lives for this call. Truncation by
+    // another process is detected below before each chunk is touched.
+    let Ok(map) = (unsafe { Mmap::map(file) }) else {
+        return Ok(None);
+    };
+
+    let mut hasher = MultiHasher::new(algorithms, key)?;
+    for chunk in map.chunks(MMAP_CHUNK_SIZE) {
+        if file.metadata()?.len() < len {
+            return Ok(None);
+        }
+        hasher.update_parallel(chunk);
+    }
+    Ok(Some(hasher.finalize()))
 }
 
 struct HashWriter {
-    hasher: Sha256,
+    hasher: MultiHasher,
 }
 
 impl Write for HashWriter {
@@ -90,13 +1690,20 @@ mod tests {
     use super::*;
     use std::io::Cursor;
 
+    fn checksum_of(input: &str, algorithm: Algorithm) -> String {
+        let mut writer = Vec::new();
+        process_checksum_internal(Cursor::new(input), "-", &mut writer, &algorithm.into()).unwrap();
+        let line = String::from_utf8(writer).unwrap();
+        line.split_whitespace().next().unwrap().to_string()
+    }
+
     #[test]
     fn test_checksum_hello() {
         let input = "hello";
         let reader = Cursor::new(input);
         let path = "test_file.txt";
         let mut writer = Vec::new();
-        process_checksum_internal(reader, path, &mut writer).unwrap();
+        process_checksum_internal(reader, path, &mut writer, &Algorithm::Sha256.into()).unwrap();
         let result = String::from_utf8(writer).unwrap();
         let expected_hash = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
         assert_eq!(result, format!("{}  {}\n", expected_hash, path));
@@ -108,9 +1715,360 @@ mod tests {
         let reader = Cursor::new(input);
         let path = "empty_file";
         let mut writer = Vec::new();
-        process_checksum_internal(reader, path, &mut writer).unwrap();
+        process_checksum_internal(reader, path, &mut writer, &Algorithm::Sha256.into()).unwrap();
         let result = String::from_utf8(writer).unwrap();
         let expected_hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
         assert_eq!(result, format!("{}  {}\n", expected_hash, path));
     }
+
+    #[test]
+    fn test_checksum_sha2_family() {
+        assert_eq!(
+            checksum_of("hello", Algorithm::Sha224),
+            "ea09ae9cc6768c50fcee903ed054556e5bfc8347907f12598aa24193"
+        );
+        assert_eq!(
+            checksum_of("hello", Algorithm::Sha512_256),
+            "e30d87cfa2a75db545eac4d61baf970366a8357c7f72fa95b52d0accb698f13a"
+        );
+    }
+
+    #[test]
+    fn test_checksum_blake3() {
+        assert_eq!(
+            checksum_of("hello", Algorithm::Blake3),
+            "ea8f163db38682925e4491c5e58d4bb3506ef8c14eb78a86e908c5624a67200f"
+        );
+    }
+
+    #[test]
+    fn test_digest_mapped_matches_streaming() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.bin");
+        // Spans several chunks and ends with a partial one
+        let content: Vec<u8> = (0..MMAP_CHUNK_SIZE as u64 * 2 + 12345)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&path, &content).unwrap();
+
+        for algorithm in [Algorithm::Sha256, Algorithm::Blake3] {
+            let file = File::open(&path).unwrap();
+            let mapped = digest_mapped(&file, &[algorithm], None).unwrap().unwrap();
+            let streaming = digest_reader(Cursor::new(&content), &[algorithm], None).unwrap();
+            assert_eq!(mapped, streaming, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_digest_mapped_falls_back_for_small_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("small.txt");
+        std::fs::write(&path, "hello").unwrap();
+        let file = File::open(&path).unwrap();
+        assert!(
+            digest_mapped(&file, &[Algorithm::Sha256], None)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    /// Yields at most `chunk` bytes per read, then fails if `fail` is set.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        chunk: usize,
+        fail: bool,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.data.is_empty() && self.fail {
+                return Err(io::Error::other("read failed"));
+            }
+            let len = buf.len().min(self.chunk).min(self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_digest_pipelined_matches_streaming() {
+        let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        for buffers in [1, 2, 4] {
+            for len in [0, 1, 63, 64, 65, 1000, 10_000] {
+                let data = &content[..len];
+                let pipeline = Pipeline {
+                    buffer_size: 64,
+                    buffers,
+                };
+                let reader = Trickle {
+                    data,
+                    chunk: 17,
+                    fail: false,
+                };
+                assert_eq!(
+                    digest_pipelined(reader, &[Algorithm::Sha256], None, pipeline).unwrap(),
+                    digest_reader(data, &[Algorithm::Sha256], None).unwrap(),
+                    "buffers = {}, len = {}",
+                    buffers,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_digest_pipelined_reports_read_errors() {
+        let content = vec![0; 1000];
+        for buffers in [1, 4] {
+            let pipeline = Pipeline {
+                buffer_size: 64,
+                buffers,
+            };
+            let reader = Trickle {
+                data: &content,
+                chunk: 64,
+                fail: true,
+            };
+            let error = digest_pipelined(reader, &[Algorithm::Sha256], None, pipeline).unwrap_err();
+            assert_eq!(error.to_string(), "read failed");
+        }
+    }
+
+    #[test]
+    fn test_checksum_non_cryptographic_is_marked() {
+        assert_eq!(
+            checksum_of("123456789", Algorithm::Crc32c),
+            "crc32c:e3069283"
+        );
+        assert_eq!(
+            checksum_of("", Algorithm::Xxh3_64),
+            "xxh3-64:2d06800538d394c2"
+        );
+        assert_eq!(
+            checksum_of("", Algorithm::Xxh3_128),
+            "xxh3-128:99aa06d3014798d86001c324468d497f"
+        );
+    }
+
+    #[test]
+    fn test_checksum_legacy() {
+        assert_eq!(
+            checksum_of("hello", Algorithm::Md5),
+            "5d41402abc4b2a76b9719d911017c592"
+        );
+        assert_eq!(
+            checksum_of("hello", Algorithm::Sha1),
+            "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+        );
+    }
+
+    #[test]
+    fn test_checksum_tag() {
+        let options = ChecksumOptions {
+            algorithms: vec![Algorithm::Sha256],
+            tag: true,
+            ..ChecksumOptions::default()
+        };
+        let mut writer = Vec::new();
+        process_checksum_internal(Cursor::new("hello"), "a.txt", &mut writer, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "SHA256 (a.txt) = 2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824\n"
+        );
+    }
+
+    #[test]
+    fn test_checksum_multiple_algorithms() {
+        let options = ChecksumOptions {
+            algorithms: vec![Algorithm::Sha256, Algorithm::Crc32c],
+            ..ChecksumOptions::default()
+        };
+        let mut writer = Vec::new();
+        process_checksum_internal(Cursor::new("hello"), "a.txt", &mut writer, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "SHA256 (a.txt) = 2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824\n\
+             CRC32C (a.txt) = 9a71bb4c\n"
+        );
+    }
+
+    #[test]
+    fn test_checksum_json() {
+        let options = ChecksumOptions {
+            algorithms: vec![Algorithm::Sha256, Algorithm::Crc32c, Algorithm::Xxh3_64],
+            json: true,
+            ..ChecksumOptions::default()
+        };
+        let mut writer = Vec::new();
+        process_checksum_internal(Cursor::new("hello"), "a \"b\".txt", &mut writer, &options)
+            .unwrap();
+        let record: serde_json::Value = serde_json::from_slice(&writer).unwrap();
+        assert_eq!(
+            record,
+            serde_json::json!({
+                "path": "a \"b\".txt",
+                "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+                "crc32c": "crc32c:9a71bb4c",
+                "xxh3-64": "xxh3-64:9555e8555c62dcfd",
+            })
+        );
+        assert_eq!(writer.iter().filter(|&&b| b == b'\n').count(), 1);
+    }
+
+    #[test]
+    fn test_checksum_keyed() {
+        // RFC 4231, test case 2
+        let options = ChecksumOptions {
+            algorithms: vec![Algorithm::Sha256],
+            tag: true,
+            key: Some(SecretKey::new(b"Jefe".to_vec()).unwrap()),
+            ..ChecksumOptions::default()
+        };
+        let mut writer = Vec::new();
+        process_checksum_internal(
+            Cursor::new("what do ya want for nothing?"),
+            "-",
+            &mut writer,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "HMAC-SHA256 (-) = 5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843\n"
+        );
+
+        let key = SecretKey::new(vec![7; 32]).unwrap();
+        let expected = blake3::keyed_hash(&[7; 32], b"hello");
+        let digests =
+            digest_reader(Cursor::new("hello"), &[Algorithm::Blake3], Some(&key)).unwrap();
+        assert_eq!(digests, vec![expected.as_bytes().to_vec()]);
+
+        let short = SecretKey::new(b"Jefe".to_vec()).unwrap();
+        assert!(MultiHasher::new(&[Algorithm::Blake3], Some(&short)).is_err());
+        assert!(MultiHasher::new(&[Algorithm::Md5], Some(&short)).is_err());
+    }
+
+    #[test]
+    fn test_checksum_encodings() {
+        let output = |options: ChecksumOptions| {
+            let mut writer = Vec::new();
+            process_checksum_internal(Cursor::new("hello"), "-", &mut writer, &options).unwrap();
+            String::from_utf8(writer).unwrap()
+        };
+        assert_eq!(
+            output(ChecksumOptions {
+                encoding: Encoding::Base64,
+                ..ChecksumOptions::default()
+            }),
+            "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=  -\n"
+        );
+        assert_eq!(
+            output(ChecksumOptions {
+                algorithms: vec![Algorithm::Crc32c],
+                encoding: Encoding::Base32,
+                ..ChecksumOptions::default()
+            }),
+            "crc32c:TJY3WTA=  -\n"
+        );
+        assert_eq!(
+            output(ChecksumOptions {
+                algorithms: vec![Algorithm::Sha256, Algorithm::Sha384],
+                sri: true,
+                ..ChecksumOptions::default()
+            }),
+            "sha256-LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ= \
+             sha384-WeF0h3dEjGnea4ANejO7+5/xtGPkQ1TDVTvNucZm+pASWjx5+QOXvfX2oT3oKGhP  -\n"
+        );
+    }
+
+    #[test]
+    fn test_checksum_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub").join("a.txt");
+        std::fs::create_dir(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "hello").unwrap();
+        let options = ChecksumOptions {
+            algorithms: vec![Algorithm::Sha256, Algorithm::Crc32c, Algorithm::Xxh3_64],
+            format: Some("{algo} {hash} {size} {path}".parse().unwrap()),
+            relative_to: Some(dir.path().to_path_buf()),
+            ..ChecksumOptions::default()
+        };
+        let mut writer = Vec::new();
+        process_inputs(&[path], &mut writer, |input, path_display, writer| {
+            process_checksum_input(input, path_display, writer, &options)
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "sha256 2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824 5 sub/a.txt\n\
+             crc32c crc32c:9a71bb4c 5 sub/a.txt\n\
+             xxh3-64 xxh3-64:9555e8555c62dcfd 5 sub/a.txt\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_checksum_cache_reuse_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        std::fs::write(&path, "hello").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(60))
+            .unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        let cache = Arc::new(cache::Cache::open(&dir.path().join("checksums")).unwrap());
+        // A wrong digest shows whether the cache was used
+        cache.store(&metadata, &metadata, &[Algorithm::Sha256], &[vec![0; 32]]);
+
+        let run = |verify_cache| {
+            let options = ChecksumOptions {
+                cache: Some(cache.clone()),
+                verify_cache,
+                ..ChecksumOptions::default()
+            };
+            let mut writer = Vec::new();
+            process_inputs(
+                std::slice::from_ref(&path),
+                &mut writer,
+                |input, path_display, writer| {
+                    process_checksum_input(input, path_display, writer, &options)
+                },
+            )
+            .unwrap();
+            String::from_utf8(writer).unwrap()
+        };
+        assert!(run(false).starts_with(&hex::encode([0; 32])));
+        assert_eq!(cache.mismatches(), 0);
+        assert!(run(true).starts_with("2cf24dba"));
+        assert_eq!(cache.mismatches(), 1);
+        assert!(run(false).starts_with("2cf24dba"));
+    }
+
+    #[test]
+    fn test_algorithm_from_tag() {
+        for algorithm in <Algorithm as clap::ValueEnum>::value_variants() {
+            assert_eq!(Algorithm::from_tag(algorithm.tag()), Some(*algorithm));
+        }
+        assert_eq!(
+            Algorithm::from_tag("SHA512/256"),
+            Some(Algorithm::Sha512_256)
+        );
+        assert_eq!(Algorithm::from_tag("sha256"), None);
+    }
+
+    #[test]
+    fn test_checksum_sha3_family() {
+        assert_eq!(
+            checksum_of("", Algorithm::Sha3_256),
+            "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a"
+        );
+        assert_eq!(
+            checksum_of("hello", Algorithm::Sha3_256),
+            "3338be694f50c5f338814986cdf0686453a888b84f424d792af4b9202398f392"
+        );
+    }
 }
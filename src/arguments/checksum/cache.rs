@@ -0,0 +1,441 @@
+//! Persistent cache of file digests for `--cache`.
+//!
+//! Digests are stored per file identity (device and inode) together with the
+//! size, modification time and change time seen when the file was hashed. A
+//! cached digest is only reused while all three are unchanged, and a file
+//! modified within `RACY_WINDOW` of being hashed is not cached at all, since
+//! a write in the same timestamp tick would go unnoticed.
+//!
+//! Each entry also records the day it was last used. Entries unused for
+//! `MAX_UNUSED_DAYS`, such as those of deleted or rewritten files, are dropped
+//! when the cache is loaded, so it doesn't grow without bound.
+//!
+//! The cache file is plain text:
+//!
+//! ```text
+//! my_app checksum cache 2
+//! <dev> <ino> <size> <mtime ns> <ctime ns> <used day> <algo> <hex digest>
+//! ...
+//! sha256 <hex digest of all lines above>
+//! ```
+//!
+//! A file that doesn't end with a matching trailer is treated as corrupt: it
+//! is ignored with a warning and replaced on the next save. Saves write a
+//! temporary file next to the cache and rename it over the old one, so a
+//! crash never leaves a half-written cache behind.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::Algorithm;
+
+/// First line of a cache file; the number is bumped on format changes.
+const HEADER: &str = "my_app checksum cache 2";
+
+/// Files modified less than this long before they are hashed aren't cached.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// Entries not used for this many days are dropped.
+const MAX_UNUSED_DAYS: u64 = 30;
+
+/// Identity of a file on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+/// Attributes that must be unchanged for a cached digest to be reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    size: u64,
+    mtime_ns: i128,
+    ctime_ns: i128,
+}
+
+#[derive(Debug)]
+struct Entry {
+    stamp: Stamp,
+    /// Day the entry was last looked up or stored, counted from the epoch.
+    used: u64,
+    digests: Vec<(Algorithm, Vec<u8>)>,
+}
+
+/// Digest cache loaded from, and saved back to, a file.
+#[derive(Debug)]
+pub struct Cache {
+    path: PathBuf,
+    entries: Mutex<HashMap<FileId, Entry>>,
+    dirty: AtomicBool,
+    mismatches: AtomicUsize,
+    /// Current day, counted from the epoch.
+    today: u64,
+}
+
+/// Returns the default cache file, `$XDG_CACHE_HOME/my_app/checksums`, falling
+/// back to `~/.cache/my_app/checksums`.
+pub fn default_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("my_app").join("checksums"))
+}
+
+/// Returns the identity and stamp of a file, or `None` if the platform can't
+/// identify files.
+#[cfg(unix)]
+fn identify(metadata: &Metadata) -> Option<(FileId, Stamp)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let nanos = |secs: i64, nsecs: i64| i128::from(secs) * 1_000_000_000 + i128::from(nsecs);
+    Some((
+        FileId {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        },
+        Stamp {
+            size: metadata.size(),
+            mtime_ns: nanos(metadata.mtime(), metadata.mtime_nsec()),
+            ctime_ns: nanos(metadata.ctime(), metadata.ctime_nsec()),
+        },
+    ))
+}
+
+#[cfg(not(unix))]
+fn identify(_metadata: &Metadata) -> Option<(FileId, Stamp)> {
+    None
+}
+
+impl Cache {
+    /// Loads the cache at `path`. A missing file gives an empty cache, and a
+    /// corrupt one is reported on stderr and replaced on the next `save`.
+    /// Entries unused for `MAX_UNUSED_DAYS` are dropped.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut entries = match std::fs::read(path) {
+            Ok(contents) => parse(&contents).unwrap_or_else(|| {
+                eprintln!(
+                    "WARNING: checksum cache {} is corrupt; rebuilding it",
+                    path.display()
+                );
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read checksum cache: {}", path.display()));
+            }
+        };
+        let today = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() / 86_400);
+        let loaded = entries.len();
+        entries.retain(|_, entry| entry.used + MAX_UNUSED_DAYS >= today);
+        Ok(Self {
+            path: path.to_path_buf(),
+            dirty: AtomicBool::new(entries.len() != loaded),
+            entries: Mutex::new(entries),
+            mismatches: AtomicUsize::new(0),
+            today,
+        })
+    }
+
+    /// Returns the cached digests of the file with `metadata`, one per
+    /// algorithm, if all of them are cached and the file is unchanged.
+    pub fn lookup(&self, metadata: &Metadata, algorithms: &[Algorithm]) -> Option<Vec<Vec<u8>>> {
+        let (id, stamp) = identify(metadata)?;
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(&id).filter(|entry| entry.stamp == stamp)?;
+        let digests: Vec<Vec<u8>> = algorithms
+            .iter()
+            .map(|algorithm| {
+                entry
+                    .digests
+                    .iter()
+                    .find(|(cached, _)| cached == algorithm)
+                    .map(|(_, digest)| digest.clone())
+            })
+            .collect::<Option<_>>()?;
+        // Only rewrite the cache for this once a day
+        if entry.used != self.today {
+            entry.used = self.today;
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        Some(digests)
+    }
+
+    /// Stores the digests of a file, given its metadata from before and after
+    /// it was hashed. Nothing is stored if the file changed in between.
+    pub fn store(
+        &self,
+        before: &Metadata,
+        after: &Metadata,
+        algorithms: &[Algorithm],
+        digests: &[Vec<u8>],
+    ) {
+        let Some((id, stamp)) = identify(before) else {
+            return;
+        };
+        let racy = before
+            .modified()
+            .is_ok_and(|mtime| mtime + RACY_WINDOW > SystemTime::now());
+        if racy || identify(after) != Some((id, stamp)) {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(id).or_insert_with(|| Entry {
+            stamp,
+            used: self.today,
+            digests: Vec::new(),
+        });
+        if entry.stamp != stamp {
+            *entry = Entry {
+                stamp,
+                used: self.today,
+                digests: Vec::new(),
+            };
+        }
+        entry.used = self.today;
+        for (algorithm, digest) in algorithms.iter().zip(digests) {
+            entry.digests.retain(|(cached, _)| cached != algorithm);
+            entry.digests.push((*algorithm, digest.clone()));
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Counts a cached digest that turned out to be wrong under
+    /// `--verify-cache`.
+    pub fn record_mismatch(&self) {
+        self.mismatches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns how many cached digests were found to be wrong.
+    pub fn mismatches(&self) -> usize {
+        self.mismatches.load(Ordering::Relaxed)
+    }
+
+    /// Writes the cache back to its file if anything changed.
+    pub fn save(&self) -> Result<()> {
+        if !self.dirty.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let contents = serialize(&self.entries.lock().unwrap());
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create cache directory: {}", dir.display()))?;
+        let file_name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = dir.join(format!(".{}.{}.tmp", file_name, std::process::id()));
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::File::create(&temp)?;
+            file.write_all(&contents)?;
+            file.sync_all()?;
+            std::fs::rename(&temp, &self.path)
+        };
+        write().map_err(|e| {
+            let _ = std::fs::remove_file(&temp);
+            anyhow::Error::from(e).context(format!(
+                "Failed to write checksum cache: {}",
+                self.path.display()
+            ))
+        })
+    }
+}
+
+/// Renders the cache file contents, including the trailer.
+fn serialize(entries: &HashMap<FileId, Entry>) -> Vec<u8> {
+    let mut contents = format!("{}\n", HEADER);
+    for (id, entry) in entries {
+        for (algorithm, digest) in &entry.digests {
+            contents.push_str(&format!(
+                "{} {} {} {} {} {} {} {}\n",
+                id.dev,
+                id.ino,
+                entry.stamp.size,
+                entry.stamp.mtime_ns,
+                entry.stamp.ctime_ns,
+                entry.used,
+                algorithm.name(),
+                hex::encode(digest)
+            ));
+        }
+    }
+    let trailer = format!("sha256 {}\n", hex::encode(Sha256::digest(&contents)));
+    contents.push_str(&trailer);
+    contents.into_bytes()
+}
+
+/// Parses cache file contents, returning `None` if they are corrupt.
+fn parse(contents: &[u8]) -> Option<HashMap<FileId, Entry>> {
+    let contents = std::str::from_utf8(contents).ok()?;
+    let body_end = contents.strip_suffix('\n')?.rfind('\n')? + 1;
+    let (body, trailer) = contents.split_at(body_end);
+    let expected = trailer.strip_prefix("sha256 ")?.strip_suffix('\n')?;
+    if hex::encode(Sha256::digest(body)) != expected {
+        return None;
+    }
+
+    let mut lines = body.lines();
+    if lines.next()? != HEADER {
+        return None;
+    }
+    let mut entries: HashMap<FileId, Entry> = HashMap::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let [dev, ino, size, mtime_ns, ctime_ns, used, algorithm, digest] = fields[..] else {
+            return None;
+        };
+        let id = FileId {
+            dev: dev.parse().ok()?,
+            ino: ino.parse().ok()?,
+        };
+        let stamp = Stamp {
+            size: size.parse().ok()?,
+            mtime_ns: mtime_ns.parse().ok()?,
+            ctime_ns: ctime_ns.parse().ok()?,
+        };
+        let used = used.parse().ok()?;
+        let algorithm = <Algorithm as clap::ValueEnum>::from_str(algorithm, false).ok()?;
+        let digest = hex::decode(digest).ok()?;
+        if digest.len() != algorithm.digest_len() {
+            return None;
+        }
+        let entry = entries.entry(id).or_insert_with(|| Entry {
+            stamp,
+            used,
+            digests: Vec::new(),
+        });
+        if entry.stamp != stamp || entry.used != used {
+            return None;
+        }
+        entry.digests.push((algorithm, digest));
+    }
+    Some(entries)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// Creates a file whose mtime is old enough to be cached.
+    fn old_file(dir: &Path, name: &str, contents: &str) -> std::fs::File {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        file
+    }
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_path = dir.path().join("cache").join("checksums");
+        let file = old_file(dir.path(), "a.txt", "hello");
+        let metadata = file.metadata().unwrap();
+        let algorithms = [Algorithm::Sha256, Algorithm::Crc32c];
+        let digests = vec![vec![1; 32], vec![2; 4]];
+
+        let cache = Cache::open(&cache_path).unwrap();
+        assert_eq!(cache.lookup(&metadata, &algorithms), None);
+        cache.store(&metadata, &metadata, &algorithms, &digests);
+        cache.save().unwrap();
+
+        let cache = Cache::open(&cache_path).unwrap();
+        assert_eq!(cache.lookup(&metadata, &algorithms), Some(digests));
+        assert_eq!(
+            cache.lookup(&metadata, &[Algorithm::Crc32c]),
+            Some(vec![vec![2; 4]])
+        );
+        assert_eq!(cache.lookup(&metadata, &[Algorithm::Blake3]), None);
+
+        // Any change to the file invalidates the entry
+        file.set_len(3).unwrap();
+        assert_eq!(cache.lookup(&file.metadata().unwrap(), &algorithms), None);
+    }
+
+    #[test]
+    fn test_cache_skips_recently_modified_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        std::fs::write(&path, "hello").unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+
+        let cache = Cache::open(&dir.path().join("checksums")).unwrap();
+        cache.store(&metadata, &metadata, &[Algorithm::Sha256], &[vec![1; 32]]);
+        assert_eq!(cache.lookup(&metadata, &[Algorithm::Sha256]), None);
+    }
+
+    #[test]
+    fn test_cache_drops_unused_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_path = dir.path().join("checksums");
+        let unused = old_file(dir.path(), "a.txt", "hello").metadata().unwrap();
+        let used = old_file(dir.path(), "b.txt", "world").metadata().unwrap();
+
+        let cache = Cache::open(&cache_path).unwrap();
+        cache.store(&unused, &unused, &[Algorithm::Sha256], &[vec![1; 32]]);
+        cache.store(&used, &used, &[Algorithm::Sha256], &[vec![2; 32]]);
+        {
+            let mut entries = cache.entries.lock().unwrap();
+            let (unused_id, _) = identify(&unused).unwrap();
+            let (used_id, _) = identify(&used).unwrap();
+            entries.get_mut(&unused_id).unwrap().used -= MAX_UNUSED_DAYS + 1;
+            entries.get_mut(&used_id).unwrap().used -= MAX_UNUSED_DAYS;
+        }
+        cache.save().unwrap();
+
+        let cache = Cache::open(&cache_path).unwrap();
+        assert_eq!(cache.lookup(&unused, &[Algorithm::Sha256]), None);
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+        // Looking an entry up keeps it for another MAX_UNUSED_DAYS
+        assert_eq!(
+            cache.lookup(&used, &[Algorithm::Sha256]),
+            Some(vec![vec![2; 32]])
+        );
+        cache.save().unwrap();
+        let saved = std::fs::read_to_string(&cache_path).unwrap();
+        assert_eq!(saved.lines().count(), 3);
+        assert!(saved.contains(&format!(" {} sha256 ", cache.today)));
+    }
+
+    #[test]
+    fn test_cache_corruption_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_path = dir.path().join("checksums");
+        let file = old_file(dir.path(), "a.txt", "hello");
+        let metadata = file.metadata().unwrap();
+
+        let cache = Cache::open(&cache_path).unwrap();
+        cache.store(&metadata, &metadata, &[Algorithm::Sha256], &[vec![1; 32]]);
+        cache.save().unwrap();
+
+        let contents = std::fs::read_to_string(&cache_path).unwrap();
+        for corrupt in [
+            contents.replace(&hex::encode([1; 32]), &hex::encode([2; 32])),
+            contents[..contents.len() - 10].to_string(),
+            String::new(),
+            "garbage".to_string(),
+        ] {
+            std::fs::write(&cache_path, corrupt).unwrap();
+            let cache = Cache::open(&cache_path).unwrap();
+            assert_eq!(cache.lookup(&metadata, &[Algorithm::Sha256]), None);
+        }
+
+        // The next save replaces the corrupt file
+        let cache = Cache::open(&cache_path).unwrap();
+        cache.store(&metadata, &metadata, &[Algorithm::Sha256], &[vec![3; 32]]);
+        cache.save().unwrap();
+        let cache = Cache::open(&cache_path).unwrap();
+        assert_eq!(
+            cache.lookup(&metadata, &[Algorithm::Sha256]),
+            Some(vec![vec![3; 32]])
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Content-defined chunking report for `--chunks`.
+//!
+//! Each input is cut into chunks with FastCDC (the 2020 variant), whose
+//! boundaries depend on the bytes around them rather than on fixed offsets,
+//! so an insertion only changes the chunks it touches. Every chunk is printed
+//! with its SHA-256 like a `--offset`/`--length` range, and a summary of the
+//! total and unique bytes across all inputs follows, estimating what
+//! chunk-level deduplication would save.
+
+use anyhow::Result;
+use fastcdc::v2020::StreamCDC;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::check::plural;
+use super::{ChecksumOptions, FileStat, range};
+use crate::utils::{Input, InputOptions, process_inputs_with};
+
+/// Smallest average chunk size FastCDC accepts.
+pub const MIN_AVG_SIZE: u32 = fastcdc::v2020::AVERAGE_MIN;
+
+/// Largest average chunk size FastCDC accepts.
+pub const MAX_AVG_SIZE: u32 = fastcdc::v2020::AVERAGE_MAX;
+
+/// Chunk counts across all inputs.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Number of chunks.
+    pub chunks: usize,
+    /// Bytes in all chunks.
+    pub bytes: u64,
+    /// Number of distinct chunks.
+    pub unique_chunks: usize,
+    /// Bytes in distinct chunks, i.e. what deduplicated storage would hold.
+    pub unique_bytes: u64,
+}
+
+/// Chunks every input and prints one record per chunk, then the summary.
+///
+/// Chunks are between a quarter of and four times `avg_size` bytes long,
+/// except for the last chunk of an input, which can be shorter.
+pub fn process_chunks<W: Write + ?Sized>(
+    files: &[PathBuf],
+    input_options: &InputOptions,
+    writer: &mut W,
+    options: &ChecksumOptions,
+    avg_size: u32,
+) -> Result<()> {
+    let seen = RefCell::new(HashSet::new());
+    let summary = RefCell::new(Summary::default());
+    process_inputs_with(
+        files,
+        input_options,
+        writer,
+        |input, path_display, writer| {
+            chunk_input(
+                input,
+                path_display,
+                &mut *writer,
+                options,
+                avg_size,
+                &mut seen.borrow_mut(),
+                &mut summary.borrow_mut(),
+            )
+        },
+    )?;
+    write_summary(writer, &summary.into_inner(), options)
+}
+
+/// Chunks one input, printing its chunks and adding them to `summary`.
+/// `seen` holds the digests of the chunks found so far.
+fn chunk_input<W: Write + ?Sized>(
+    input: Input,
+    path_display: &str,
+    writer: &mut W,
+    options: &ChecksumOptions,
+    avg_size: u32,
+    seen: &mut HashSet<[u8; 32]>,
+    summary: &mut Summary,
+) -> Result<()> {
+    for chunk in StreamCDC::new(input, avg_size / 4, avg_size, avg_size * 4) {
+        let chunk = chunk.map_err(std::io::Error::from)?;
+        let digest: [u8; 32] = Sha256::digest(&chunk.data).into();
+        let length = chunk.length as u64;
+        range::write_range_record(
+            &mut *writer,
+            &[digest.to_vec()],
+            path_display,
+            chunk.offset,
+            length,
+            FileStat::default(),
+            options,
+        )?;
+        summary.chunks += 1;
+        summary.bytes += length;
+        if seen.insert(digest) {
+            summary.unique_chunks += 1;
+            summary.unique_bytes += length;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the summary as a line, or as a JSON record with `json`.
+fn write_summary<W: Write + ?Sized>(
+    writer: &mut W,
+    summary: &Summary,
+    options: &ChecksumOptions,
+) -> Result<()> {
+    if options.json {
+        let record = serde_json::json!({
+            "chunks": summary.chunks,
+            "bytes": summary.bytes,
+            "unique_chunks": summary.unique_chunks,
+            "unique_bytes": summary.unique_bytes,
+        });
+        writeln!(writer, "{}", record)?;
+        return Ok(());
+    }
+    let saved = match summary.bytes {
+        0 => 0.0,
+        bytes => (bytes - summary.unique_bytes) as f64 * 100.0 / bytes as f64,
+    };
+    write!(
+        writer,
+        "{}, {} bytes; {} unique, {} bytes ({:.1}% saved)",
+        plural(summary.chunks, "chunk", "chunks"),
+        summary.bytes,
+        summary.unique_chunks,
+        summary.unique_bytes,
+        saved
+    )?;
+    writer.write_all(if options.zero { b"\0" } else { b"\n" })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_chunks_deduplicate_shifted_data() {
+        // Pseudo-random bytes, so chunk boundaries depend on the contents
+        let mut state = 1u64;
+        let data: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect();
+        let mut shifted = b"inserted".to_vec();
+        shifted.extend_from_slice(&data);
+
+        let options = ChecksumOptions::default();
+        let mut seen = HashSet::new();
+        let mut summary = Summary::default();
+        let mut writer = Vec::new();
+        for (input, name) in [(data.clone(), "a"), (shifted, "b")] {
+            let input = Input::SymlinkTarget(Cursor::new(input));
+            chunk_input(
+                input,
+                name,
+                &mut writer,
+                &options,
+                MIN_AVG_SIZE * 16,
+                &mut seen,
+                &mut summary,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(summary.bytes, 400_008);
+        assert!(summary.chunks > 2 * 20);
+        assert_eq!(
+            writer.iter().filter(|&&b| b == b'\n').count(),
+            summary.chunks
+        );
+        // Only the chunk the insertion falls into differs
+        assert_eq!(summary.unique_chunks, summary.chunks / 2 + 1);
+        assert!(summary.unique_bytes < 200_000 + 4 * MIN_AVG_SIZE as u64 * 16);
+
+        let first = String::from_utf8(writer).unwrap();
+        let first = first.lines().next().unwrap();
+        let (digest, name) = first.split_once("  ").unwrap();
+        assert!(name.starts_with("a@0+"));
+        let length: usize = name["a@0+".len()..].parse().unwrap();
+        assert_eq!(digest, hex::encode(Sha256::digest(&data[..length])));
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = Summary {
+            chunks: 4,
+            bytes: 400,
+            unique_chunks: 3,
+            unique_bytes: 300,
+        };
+        let mut writer = Vec::new();
+        write_summary(&mut writer, &summary, &ChecksumOptions::default()).unwrap();
+        let options = ChecksumOptions {
+            json: true,
+            ..ChecksumOptions::default()
+        };
+        write_summary(&mut writer, &summary, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "4 chunks, 400 bytes; 3 unique, 300 bytes (25.0% saved)\n\
+             {\"bytes\":400,\"chunks\":4,\"unique_bytes\":300,\"unique_chunks\":3}\n"
+        );
+    }
+}
@@ -0,0 +1,663 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+
+use super::escape::{display_filename, unescape_filename};
+use super::{Algorithm, Pipeline, SecretKey, digest_input, encoding};
+use crate::utils::Input;
+
+/// Options controlling how `process_check_internal` verifies a manifest.
+#[derive(Debug, Clone, Default)]
+pub struct CheckOptions {
+    /// Algorithm assumed for lines that don't name one.
+    pub algorithm: Algorithm,
+    /// Don't print `OK` for files that match.
+    pub quiet: bool,
+    /// Don't print anything; only the exit status reports the outcome.
+    pub status: bool,
+    /// Warn about every improperly formatted line.
+    pub warn: bool,
+    /// Silently skip files that don't exist.
+    pub ignore_missing: bool,
+    /// Verify lines tagged with the broken MD5 and SHA-1 algorithms.
+    pub allow_weak: bool,
+    /// Buffers used to overlap reading and hashing listed files.
+    pub pipeline: Pipeline,
+    /// Key for keyed lines; untagged lines are keyed if it is set.
+    pub key: Option<SecretKey>,
+    /// Lines end with NUL instead of a newline, and names are not escaped.
+    pub zero: bool,
+}
+
+/// Counters collected while verifying one manifest.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CheckSummary {
+    /// Lines that could be parsed as checksum lines.
+    pub properly_formatted: usize,
+    /// Lines that could not be parsed.
+    pub improperly_formatted: usize,
+    /// Listed files that were read and hashed.
+    pub verified: usize,
+    /// Listed files whose checksum did not match.
+    pub mismatched: usize,
+    /// Listed files that could not be opened or read.
+    pub unreadable: usize,
+}
+
+impl CheckSummary {
+    /// Returns `true` if the manifest should make the command fail.
+    pub fn is_failure(&self, options: &CheckOptions) -> bool {
+        self.properly_formatted == 0
+            || self.mismatched > 0
+            || self.unreadable > 0
+            || (options.ignore_missing && self.verified == 0)
+    }
+}
+
+/// A single parsed line of a checksum manifest.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Algorithm the digest was computed with.
+    pub algorithm: Algorithm,
+    /// Whether the digest is keyed (HMAC or keyed BLAKE3).
+    pub keyed: bool,
+    /// Raw digest bytes.
+    pub digest: Vec<u8>,
+    /// Path of the listed file.
+    pub path: String,
+}
+
+/// Parses one manifest line in GNU, BSD or SRI layout.
+///
+/// GNU lines (`<hex>  <path>` or `<hex> *<path>`) are read as `default`,
+/// keyed if `keyed` is set; BSD lines (`TAG (path) = <hex>`) name their own
+/// algorithm and whether it is keyed, and SRI lines (`<integrity>  <path>`)
+/// are checked against their strongest hash. Digests may use any encoding
+/// `--encoding` can print. Returns `None` if the line is not a valid
+/// checksum line.
+pub fn parse_manifest_line(line: &str, default: Algorithm, keyed: bool) -> Option<ManifestEntry> {
+    parse_sri_line(line)
+        .or_else(|| parse_gnu_line(line, default, keyed))
+        .or_else(|| parse_bsd_line(line))
+}
+
+/// Parses one manifest line like `parse_manifest_line`, undoing the escaping
+/// of a line that starts with `\` unless records are NUL-terminated (`zero`).
+pub fn parse_record(
+    line: &str,
+    default: Algorithm,
+    keyed: bool,
+    zero: bool,
+) -> Option<ManifestEntry> {
+    match line.strip_prefix('\\') {
+        Some(escaped) if !zero => {
+            let mut entry = parse_manifest_line(escaped, default, keyed)?;
+            entry.path = unescape_filename(&entry.path)?;
+            Some(entry)
+        }
+        _ => parse_manifest_line(line, default, keyed),
+    }
+}
+
+/// Parses an `<integrity>  <path>` line written with `--sri`.
+fn parse_sri_line(line: &str) -> Option<ManifestEntry> {
+    let (integrity, path) = line.split_once("  ")?;
+    if path.is_empty() {
+        return None;
+    }
+    let (algorithm, digest) = encoding::parse_sri(integrity)?;
+    Some(ManifestEntry {
+        algorithm,
+        keyed: false,
+        digest,
+        path: path.to_string(),
+    })
+}
+
+/// Parses a BSD-style `TAG (path) = <digest>` line.
+fn parse_bsd_line(line: &str) -> Option<ManifestEntry> {
+    let (tag, rest) = line.split_once(" (")?;
+    let (path, digest) = rest.rsplit_once(") = ")?;
+    if path.is_empty() {
+        return None;
+    }
+    let (algorithm, keyed) = match Algorithm::from_keyed_tag(tag) {
+        Some(algorithm) => (algorithm, true),
+        None => (Algorithm::from_tag(tag)?, false),
+    };
+    let digest = encoding::decode(digest, algorithm.digest_len())?;
+    Some(ManifestEntry {
+        algorithm,
+        keyed,
+        digest,
+        path: path.to_string(),
+    })
+}
+
+/// Parses a GNU-style `<digest>  <path>` (or `<digest> *<path>`) line.
+fn parse_gnu_line(line: &str, default: Algorithm, keyed: bool) -> Option<ManifestEntry> {
+    let (digest, rest) = line.split_once(' ')?;
+    let path = rest.strip_prefix([' ', '*'])?;
+    if path.is_empty() {
+        return None;
+    }
+    let (algorithm, digest) = Algorithm::parse_digest(digest, default)?;
+    Some(ManifestEntry {
+        algorithm,
+        keyed: keyed && algorithm.supports_key(),
+        digest,
+        path: path.to_string(),
+    })
+}
+
+/// Verifies every file listed in a checksum manifest.
+///
+/// Prints `<path>: OK` or `<path>: FAILED` for each listed file and a summary
+/// of problems on stderr, following GNU `sha256sum --check`.
+///
+/// # Arguments
+///
+/// * `reader` - Manifest reader.
+/// * `manifest_display` - Display string for the manifest path (or "-" for stdin).
+/// * `writer` - Output writer.
+/// * `options` - Verification options.
+pub fn process_check_internal<R: Read, W: Write>(
+    reader: R,
+    manifest_display: &str,
+    mut writer: W,
+    options: &CheckOptions,
+) -> Result<CheckSummary> {
+    let mut summary = CheckSummary::default();
+    let mut reader = BufReader::new(reader);
+    let mut buffer = Vec::new();
+    let mut line_number = 0;
+
+    loop {
+        buffer.clear();
+        let end = if options.zero { b'\0' } else { b'\n' };
+        if reader.read_until(end, &mut buffer)? == 0 {
+            break;
+        }
+        line_number += 1;
+        if buffer.last() == Some(&end) {
+            buffer.pop();
+        }
+
+        let entry = std::str::from_utf8(&buffer).ok().and_then(|line| {
+            parse_record(line, options.algorithm, options.key.is_some(), options.zero)
+        });
+        let Some(entry) = entry else {
+            summary.improperly_formatted += 1;
+            if options.warn && !options.status {
+                eprintln!(
+                    "{}: {}: improperly formatted {} checksum line",
+                    manifest_display,
+                    line_number,
+                    options.algorithm.name()
+                );
+            }
+            continue;
+        };
+        summary.properly_formatted += 1;
+        let shown = display_filename(&entry.path);
+
+        if entry.algorithm.is_weak() && !options.allow_weak {
+            summary.unreadable += 1;
+            if !options.status {
+                eprintln!(
+                    "{}: {}: {} is cryptographically broken; pass --allow-weak-hashes to verify it",
+                    manifest_display,
+                    line_number,
+                    entry.algorithm.name()
+                );
+                writeln!(writer, "{}: FAILED open or read", shown)?;
+            }
+            continue;
+        }
+
+        let key = match (entry.keyed, &options.key) {
+            (false, _) => None,
+            (true, Some(key)) => Some(key),
+            (true, None) => {
+                summary.unreadable += 1;
+                if !options.status {
+                    eprintln!(
+                        "{}: {}: keyed {} line needs --key-file, --key-fd or --key-env",
+                        manifest_display,
+                        line_number,
+                        entry.algorithm.name()
+                    );
+                    writeln!(writer, "{}: FAILED open or read", shown)?;
+                }
+                continue;
+            }
+        };
+
+        let path = PathBuf::from(&entry.path);
+        let digest = match File::open(&path) {
+            Err(e) if options.ignore_missing && e.kind() == std::io::ErrorKind::NotFound => {
+                continue;
+            }
+            Err(e) => Err(e),
+            Ok(file) => {
+                let mut input = Input::File {
+                    path,
+                    reader: BufReader::new(file),
+                };
+                digest_input(&mut input, &[entry.algorithm], key, options.pipeline)
+                    .map(|mut digests| digests.remove(0))
+            }
+        };
+
+        match digest {
+            Err(e) => {
+                summary.unreadable += 1;
+                if !options.status {
+                    eprintln!("{}: {}", shown, e);
+                    writeln!(writer, "{}: FAILED open or read", shown)?;
+                }
+            }
+            Ok(digest) if digest == entry.digest => {
+                summary.verified += 1;
+                if !options.quiet && !options.status {
+                    writeln!(writer, "{}: OK", shown)?;
+                }
+            }
+            Ok(_) => {
+                summary.verified += 1;
+                summary.mismatched += 1;
+                if !options.status {
+                    writeln!(writer, "{}: FAILED", shown)?;
+                }
+            }
+        }
+    }
+
+    if !options.status {
+        report_summary(&summary, manifest_display, options);
+    }
+    Ok(summary)
+}
+
+/// Prints the end-of-manifest warnings on stderr.
+fn report_summary(summary: &CheckSummary, manifest_display: &str, options: &CheckOptions) {
+    if summary.properly_formatted == 0 {
+        eprintln!(
+            "{}: no properly formatted checksum lines found",
+            manifest_display
+        );
+        return;
+    }
+    if summary.improperly_formatted > 0 {
+        eprintln!(
+            "WARNING: {}",
+            plural(
+                summary.improperly_formatted,
+                "line is improperly formatted",
+                "lines are improperly formatted"
+            )
+        );
+    }
+    if summary.unreadable > 0 {
+        eprintln!(
+            "WARNING: {}",
+            plural(
+                summary.unreadable,
+                "listed file could not be read",
+                "listed files could not be read"
+            )
+        );
+    }
+    if summary.mismatched > 0 {
+        eprintln!(
+            "WARNING: {}",
+            plural(
+                summary.mismatched,
+                "computed checksum did NOT match",
+                "computed checksums did NOT match"
+            )
+        );
+    }
+    if options.ignore_missing && summary.verified == 0 {
+        eprintln!("{}: no file was verified", manifest_display);
+    }
+}
+
+/// Formats `count` followed by the singular or plural noun.
+pub fn plural(count: usize, singular: &str, plural: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, singular)
+    } else {
+        format!("{} {}", count, plural)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_parse_manifest_line() {
+        let entry = parse_manifest_line(
+            &format!("{}  a b.txt", HELLO_SHA256),
+            Algorithm::Sha256,
+            false,
+        )
+        .unwrap();
+        assert_eq!(entry.algorithm, Algorithm::Sha256);
+        assert_eq!(entry.path, "a b.txt");
+
+        let binary =
+            parse_manifest_line(&format!("{} *file", HELLO_SHA256), Algorithm::Sha256, false)
+                .unwrap();
+        assert_eq!(binary.path, "file");
+
+        let crc = parse_manifest_line("crc32c:e3069283  file", Algorithm::Sha256, false).unwrap();
+        assert_eq!(crc.algorithm, Algorithm::Crc32c);
+
+        assert!(
+            parse_manifest_line(&format!("{}  file", HELLO_SHA256), Algorithm::Sha512, false)
+                .is_none()
+        );
+        assert!(
+            parse_manifest_line(&format!("{}file", HELLO_SHA256), Algorithm::Sha256, false)
+                .is_none()
+        );
+        assert!(
+            parse_manifest_line(&format!("{}  ", HELLO_SHA256), Algorithm::Sha256, false).is_none()
+        );
+        assert!(parse_manifest_line("sha256:00  file", Algorithm::Sha256, false).is_none());
+    }
+
+    #[test]
+    fn test_parse_manifest_line_bsd() {
+        let entry = parse_manifest_line(
+            &format!("SHA256 (dir/a (1).txt) = {}", HELLO_SHA256),
+            Algorithm::Sha512,
+            false,
+        )
+        .unwrap();
+        assert_eq!(entry.algorithm, Algorithm::Sha256);
+        assert_eq!(entry.path, "dir/a (1).txt");
+        assert!(!entry.keyed);
+
+        // Tagged lines say whether they are keyed, regardless of `keyed`
+        let hmac = parse_manifest_line(
+            &format!("HMAC-SHA256 (f) = {}", HELLO_SHA256),
+            Algorithm::Sha256,
+            false,
+        )
+        .unwrap();
+        assert_eq!(hmac.algorithm, Algorithm::Sha256);
+        assert!(hmac.keyed);
+        let plain = parse_manifest_line(
+            &format!("SHA256 (f) = {}", HELLO_SHA256),
+            Algorithm::Sha256,
+            true,
+        )
+        .unwrap();
+        assert!(!plain.keyed);
+
+        let md5 = parse_manifest_line(
+            "MD5 (f) = 5d41402abc4b2a76b9719d911017c592",
+            Algorithm::Sha256,
+            false,
+        )
+        .unwrap();
+        assert_eq!(md5.algorithm, Algorithm::Md5);
+
+        assert!(
+            parse_manifest_line(
+                &format!("SHA512 (f) = {}", HELLO_SHA256),
+                Algorithm::Sha256,
+                false
+            )
+            .is_none()
+        );
+        assert!(
+            parse_manifest_line(
+                &format!("FOO (f) = {}", HELLO_SHA256),
+                Algorithm::Sha256,
+                false
+            )
+            .is_none()
+        );
+        assert!(
+            parse_manifest_line(
+                &format!("SHA256 () = {}", HELLO_SHA256),
+                Algorithm::Sha256,
+                false
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_check_mixed_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let gnu = dir.path().join("gnu.txt");
+        let bsd = dir.path().join("bsd.txt");
+        let weak = dir.path().join("weak.txt");
+        std::fs::write(&gnu, "hello").unwrap();
+        std::fs::write(&bsd, "hello").unwrap();
+        std::fs::write(&weak, "hello").unwrap();
+        let manifest = format!(
+            "{}  {}\nSHA3-256 ({}) = 3338be694f50c5f338814986cdf0686453a888b84f424d792af4b9202398f392\nMD5 ({}) = 5d41402abc4b2a76b9719d911017c592\n",
+            HELLO_SHA256,
+            gnu.display(),
+            bsd.display(),
+            weak.display()
+        );
+
+        let options = CheckOptions::default();
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options).unwrap();
+        assert_eq!(summary.verified, 2);
+        assert_eq!(summary.unreadable, 1);
+
+        let options = CheckOptions {
+            allow_weak: true,
+            ..CheckOptions::default()
+        };
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options).unwrap();
+        assert_eq!(summary.verified, 3);
+        assert!(!summary.is_failure(&options));
+    }
+
+    #[test]
+    fn test_check_reports_ok_and_failed() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.txt");
+        let bad = dir.path().join("bad.txt");
+        std::fs::write(&good, "hello").unwrap();
+        std::fs::write(&bad, "tampered").unwrap();
+        let manifest = format!(
+            "{hash}  {}\n{hash}  {}\nnot a checksum line\n",
+            good.display(),
+            bad.display(),
+            hash = HELLO_SHA256
+        );
+
+        let options = CheckOptions::default();
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(manifest), "SUMS", &mut writer, &options).unwrap();
+        let output = String::from_utf8(writer).unwrap();
+
+        assert_eq!(
+            output,
+            format!("{}: OK\n{}: FAILED\n", good.display(), bad.display())
+        );
+        assert_eq!(summary.verified, 2);
+        assert_eq!(summary.mismatched, 1);
+        assert_eq!(summary.improperly_formatted, 1);
+        assert!(summary.is_failure(&options));
+    }
+
+    #[test]
+    fn test_check_keyed_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        std::fs::write(&file, "what do ya want for nothing?").unwrap();
+        let manifest = format!(
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843  {}\n",
+            file.display()
+        );
+
+        let options = CheckOptions {
+            key: Some(SecretKey::new(b"Jefe".to_vec()).unwrap()),
+            ..CheckOptions::default()
+        };
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options).unwrap();
+        assert_eq!(summary.verified, 1);
+        assert!(!summary.is_failure(&options));
+
+        // Without the key, the same line is a plain SHA-256 that doesn't match
+        let options = CheckOptions::default();
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options).unwrap();
+        assert_eq!(summary.mismatched, 1);
+
+        // Tagged keyed lines need a key
+        let manifest = format!(
+            "HMAC-SHA256 ({}) = 5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843\n",
+            file.display()
+        );
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options).unwrap();
+        assert_eq!(summary.unreadable, 1);
+    }
+
+    #[test]
+    fn test_check_any_encoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        std::fs::write(&file, "hello").unwrap();
+        let manifest = format!(
+            "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=  {path}\n\
+             SHA256 ({path}) = LPJNul-wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ\n\
+             FTZE3OS7WCRQ4JXIHMVMLOPCTYNRMHS4D6TUEXTTAQZWFE4LTASA====  {path}\n\
+             sha384-WeF0h3dEjGnea4ANejO7+5/xtGPkQ1TDVTvNucZm+pASWjx5+QOXvfX2oT3oKGhP  {path}\n",
+            path = file.display()
+        );
+
+        let options = CheckOptions::default();
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options).unwrap();
+        assert_eq!(summary.improperly_formatted, 0);
+        assert_eq!(summary.verified, 4);
+        assert!(!summary.is_failure(&options));
+    }
+
+    #[test]
+    fn test_check_refuses_digest_of_another_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        std::fs::write(&file, "hello").unwrap();
+        let manifest = format!(
+            "{hash}  {path}\nSHA384 ({path}) = {hash}\n",
+            hash = HELLO_SHA256,
+            path = file.display()
+        );
+
+        let options = CheckOptions {
+            algorithm: Algorithm::Sha384,
+            ..CheckOptions::default()
+        };
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options).unwrap();
+        assert_eq!(summary.improperly_formatted, 2);
+        assert_eq!(summary.mismatched, 0);
+        assert!(summary.is_failure(&options));
+    }
+
+    #[test]
+    fn test_check_escaped_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a\\b");
+        std::fs::write(&file, "hello").unwrap();
+        let escaped = file.display().to_string().replace('\\', "\\\\");
+        let manifest = format!(
+            "\\SHA256 ({escaped}) = {HELLO_SHA256}\n\\{HELLO_SHA256}  {escaped}\n\\{HELLO_SHA256}  bad\\q\n"
+        );
+
+        let options = CheckOptions::default();
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options).unwrap();
+        assert_eq!(summary.verified, 2);
+        assert_eq!(summary.improperly_formatted, 1);
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            format!("\\{escaped}: OK\n").repeat(2)
+        );
+    }
+
+    #[test]
+    fn test_check_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.txt");
+        let manifest = format!("{}  {}\n", HELLO_SHA256, missing.display());
+
+        let options = CheckOptions::default();
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options).unwrap();
+        assert_eq!(summary.unreadable, 1);
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            format!("{}: FAILED open or read\n", missing.display())
+        );
+
+        let options = CheckOptions {
+            ignore_missing: true,
+            ..CheckOptions::default()
+        };
+        let mut writer = Vec::new();
+        let summary =
+            process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options).unwrap();
+        assert_eq!(summary.unreadable, 0);
+        assert!(writer.is_empty());
+        // Nothing was verified, which still counts as a failure.
+        assert!(summary.is_failure(&options));
+    }
+
+    #[test]
+    fn test_check_quiet_and_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.txt");
+        std::fs::write(&good, "hello").unwrap();
+        let manifest = format!("{}  {}\n", HELLO_SHA256, good.display());
+
+        for options in [
+            CheckOptions {
+                quiet: true,
+                ..CheckOptions::default()
+            },
+            CheckOptions {
+                status: true,
+                ..CheckOptions::default()
+            },
+        ] {
+            let mut writer = Vec::new();
+            let summary =
+                process_check_internal(Cursor::new(&manifest), "SUMS", &mut writer, &options)
+                    .unwrap();
+            assert!(writer.is_empty());
+            assert!(!summary.is_failure(&options));
+        }
+    }
+}
@@ -0,0 +1,428 @@
+//! Resumable hashing of long streams for `--checkpoint` and `--resume`.
+//!
+//! While hashing, the internal state of every hasher and the number of bytes
+//! hashed so far are written to a checkpoint file every `interval` bytes.
+//! `--resume` restores the hashers from it and continues at that offset:
+//! files are seeked, and stdin is read and discarded up to it, so the input
+//! must be the same stream from its beginning. The final digest is the same
+//! as that of a single uninterrupted run.
+//!
+//! A file input must also have the size and modification time it had when
+//! the checkpoint was saved, so an edited file is not resumed with the state
+//! of its old contents.
+//!
+//! The file is text: a header line, the escaped input name, the offset, the
+//! input's `<size> <mtime ns>` (`-` for stdin) and one `<algorithm> <hex
+//! state>` line per algorithm, followed by a SHA-256 trailer over everything
+//! before it, like the checksum cache.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::escape::display_filename;
+use super::{Algorithm, ChecksumOptions, FileStat, MultiHasher, range, read_retrying};
+use crate::utils::{Input, process_inputs};
+
+/// First line of every checkpoint file; the number is the format version.
+const HEADER: &str = "my_app checkpoint 1";
+
+/// Where and how often the hash state is saved.
+#[derive(Debug, Clone)]
+pub struct CheckpointOptions {
+    /// Checkpoint file.
+    pub path: PathBuf,
+    /// Bytes hashed between two saves.
+    pub interval: u64,
+    /// Continue from the existing checkpoint file.
+    pub resume: bool,
+}
+
+/// Size and modification time in nanoseconds since the epoch of a file.
+type Stamp = (u64, u128);
+
+/// Returns the stamp of a file input, or `None` for stdin.
+fn stamp(input: &Input) -> io::Result<Option<Stamp>> {
+    let Input::File { reader, .. } = input else {
+        return Ok(None);
+    };
+    let metadata = reader.get_ref().metadata()?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    Ok(Some((metadata.len(), mtime)))
+}
+
+/// Hash state saved in a checkpoint file.
+#[derive(Debug, PartialEq, Eq)]
+struct Saved {
+    /// Input as displayed, escaped like in checksum lines.
+    input: String,
+    /// Bytes hashed so far.
+    offset: u64,
+    /// Size and modification time of a file input, see `stamp`.
+    stamp: Option<Stamp>,
+    /// One state per algorithm, see `MultiHasher::save_state`.
+    states: Vec<(Algorithm, Vec<u8>)>,
+}
+
+/// Hashes the single input in `files` (or stdin) with checkpoints, prints
+/// its record and removes the checkpoint once the digest is complete.
+pub fn process_checkpointed<W: Write + ?Sized>(
+    files: &[PathBuf],
+    writer: &mut W,
+    options: &ChecksumOptions,
+    checkpoint: &CheckpointOptions,
+) -> Result<()> {
+    if files.len() > 1 {
+        anyhow::bail!("--checkpoint hashes a single file or stdin");
+    }
+    if let Some(algorithm) = options
+        .algorithms
+        .iter()
+        .find(|algorithm| !algorithm.supports_checkpoint())
+    {
+        anyhow::bail!(
+            "{} cannot be checkpointed; use a SHA-2, SHA-3, MD5, SHA-1 or CRC32C algorithm",
+            algorithm.name()
+        );
+    }
+    if !checkpoint.resume && checkpoint.path.exists() {
+        anyhow::bail!(
+            "{} already exists; pass --resume to continue from it or remove it",
+            checkpoint.path.display()
+        );
+    }
+
+    process_inputs(files, writer, |mut input, path_display, writer| {
+        let digests = digest_checkpointed(&mut input, path_display, options, checkpoint)?;
+        let stat = match &input {
+            Input::File { reader, .. } if options.needs_stat() => {
+                FileStat::from(&reader.get_ref().metadata()?)
+            }
+            _ => FileStat::default(),
+        };
+        options.write_record(&mut *writer, &digests, path_display, stat)?;
+        // Inputs shorter than the interval never saved a checkpoint
+        match std::fs::remove_file(&checkpoint.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(anyhow::Error::from(e).context(
+                format!("Failed to remove checkpoint: {}", checkpoint.path.display()),
+            )),
+            _ => Ok(()),
+        }
+    })
+}
+
+/// Returns the digests of `input`, starting from the checkpoint with
+/// `--resume` and saving one every `interval` bytes.
+fn digest_checkpointed(
+    input: &mut Input,
+    path_display: &str,
+    options: &ChecksumOptions,
+    checkpoint: &CheckpointOptions,
+) -> Result<Vec<Vec<u8>>> {
+    let algorithms = &options.algorithms;
+    let name = display_filename(path_display);
+    let stamp = stamp(input)?;
+    let (mut hasher, mut offset) = if checkpoint.resume {
+        let saved = load(&checkpoint.path)?;
+        if saved.input != name {
+            anyhow::bail!(
+                "{} was saved while hashing {}, not {}",
+                checkpoint.path.display(),
+                saved.input,
+                name
+            );
+        }
+        if saved.stamp != stamp {
+            anyhow::bail!(
+                "{} changed since {} was saved",
+                path_display,
+                checkpoint.path.display()
+            );
+        }
+        let saved_algorithms: Vec<_> = saved
+            .states
+            .iter()
+            .map(|(algorithm, _)| *algorithm)
+            .collect();
+        if saved_algorithms != *algorithms {
+            let names: Vec<_> = saved_algorithms
+                .iter()
+                .map(|algorithm| algorithm.name())
+                .collect();
+            anyhow::bail!(
+                "{} was saved with --algo {}",
+                checkpoint.path.display(),
+                names.join(",")
+            );
+        }
+        let states: Vec<_> = saved.states.into_iter().map(|(_, state)| state).collect();
+        let hasher = MultiHasher::restore(algorithms, &states)
+            .with_context(|| format!("Invalid checkpoint: {}", checkpoint.path.display()))?;
+        if range::skip_to(input, saved.offset)? < saved.offset {
+            anyhow::bail!(
+                "{} is shorter than the {} bytes already hashed",
+                path_display,
+                saved.offset
+            );
+        }
+        eprintln!("{}: resuming at byte {}", path_display, saved.offset);
+        (hasher, saved.offset)
+    } else {
+        (MultiHasher::new(algorithms, None)?, 0)
+    };
+
+    let mut buffer = vec![0; options.pipeline.buffer_size.max(1)];
+    let mut unsaved = 0;
+    loop {
+        let len = read_retrying(input, &mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buffer[..len]);
+        offset += len as u64;
+        unsaved += len as u64;
+        if unsaved >= checkpoint.interval {
+            let states = hasher
+                .save_state()
+                .expect("checkpointed algorithms can save their state");
+            let saved = Saved {
+                input: name.clone(),
+                offset,
+                stamp,
+                states: algorithms.iter().copied().zip(states).collect(),
+            };
+            save(&checkpoint.path, &saved)?;
+            unsaved = 0;
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+/// Renders the checkpoint file contents, including the trailer.
+fn serialize(saved: &Saved) -> String {
+    let stamp = match saved.stamp {
+        Some((size, mtime)) => format!("{} {}", size, mtime),
+        None => "-".to_string(),
+    };
+    let mut contents = format!("{}\n{}\n{}\n{}\n", HEADER, saved.input, saved.offset, stamp);
+    for (algorithm, state) in &saved.states {
+        contents.push_str(&format!("{} {}\n", algorithm.name(), hex::encode(state)));
+    }
+    let trailer = format!("sha256 {}\n", hex::encode(Sha256::digest(&contents)));
+    contents.push_str(&trailer);
+    contents
+}
+
+/// Parses checkpoint file contents, or returns `None` if they are damaged.
+fn deserialize(contents: &str) -> Option<Saved> {
+    let (body, trailer) = contents.strip_suffix('\n')?.rsplit_once('\n')?;
+    let body = format!("{}\n", body);
+    if trailer != format!("sha256 {}", hex::encode(Sha256::digest(&body))) {
+        return None;
+    }
+    let mut lines = body.lines();
+    if lines.next()? != HEADER {
+        return None;
+    }
+    let input = lines.next()?.to_string();
+    let offset = lines.next()?.parse().ok()?;
+    let stamp = match lines.next()? {
+        "-" => None,
+        stamp => {
+            let (size, mtime) = stamp.split_once(' ')?;
+            Some((size.parse().ok()?, mtime.parse().ok()?))
+        }
+    };
+    let states = lines
+        .map(|line| {
+            let (name, state) = line.split_once(' ')?;
+            let algorithm = <Algorithm as clap::ValueEnum>::from_str(name, false).ok()?;
+            Some((algorithm, hex::decode(state).ok()?))
+        })
+        .collect::<Option<_>>()?;
+    Some(Saved {
+        input,
+        offset,
+        stamp,
+        states,
+    })
+}
+
+/// Reads a checkpoint file.
+fn load(path: &Path) -> Result<Saved> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read checkpoint: {}", path.display()))?;
+    deserialize(&contents).with_context(|| format!("Invalid checkpoint: {}", path.display()))
+}
+
+/// Replaces the checkpoint file atomically, so an interruption while saving
+/// leaves the previous checkpoint intact.
+fn save(path: &Path, saved: &Saved) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = dir.join(format!(".{}.{}.tmp", file_name, std::process::id()));
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(serialize(saved).as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temp, path)
+    };
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&temp);
+        anyhow::Error::from(e).context(format!("Failed to write checkpoint: {}", path.display()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn test_every_checkpointed_algorithm_restores() {
+        let algorithms: Vec<_> = <Algorithm as clap::ValueEnum>::value_variants()
+            .iter()
+            .copied()
+            .filter(|algorithm| algorithm.supports_checkpoint())
+            .collect();
+        let mut hasher = MultiHasher::new(&algorithms, None).unwrap();
+        hasher.update(b"hello ");
+        let states = hasher.save_state().unwrap();
+        let mut restored = MultiHasher::restore(&algorithms, &states).unwrap();
+        hasher.update(b"world");
+        restored.update(b"world");
+        assert_eq!(restored.finalize(), hasher.finalize());
+
+        assert!(
+            MultiHasher::new(&[Algorithm::Blake3], None)
+                .unwrap()
+                .save_state()
+                .is_none()
+        );
+        assert!(Algorithm::Sha256.restore_hasher(&[0; 3]).is_none());
+    }
+
+    #[test]
+    fn test_resume_matches_single_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8).collect();
+        let options = ChecksumOptions {
+            algorithms: vec![Algorithm::Sha256, Algorithm::Sha3_256],
+            pipeline: super::super::Pipeline {
+                buffer_size: 4096,
+                buffers: 1,
+            },
+            ..ChecksumOptions::default()
+        };
+        let checkpoint = CheckpointOptions {
+            path: dir.path().join("data.checkpoint"),
+            interval: 10_000,
+            resume: false,
+        };
+        let display = "-";
+
+        // A run that stopped after 50000 bytes; the last save was at 49152
+        let mut partial = Input::SymlinkTarget(Cursor::new(data[..50_000].to_vec()));
+        digest_checkpointed(&mut partial, display, &options, &checkpoint).unwrap();
+        let saved = load(&checkpoint.path).unwrap();
+        assert_eq!(saved.offset, 49_152);
+
+        let resume = CheckpointOptions {
+            resume: true,
+            ..checkpoint.clone()
+        };
+        // Streams are fed again from the start
+        let mut input = Input::SymlinkTarget(Cursor::new(data.clone()));
+        let resumed = digest_checkpointed(&mut input, display, &options, &resume).unwrap();
+        let whole = MultiHasher::new(&options.algorithms, None).map(|mut hasher| {
+            hasher.update(&data);
+            hasher.finalize()
+        });
+        assert_eq!(resumed, whole.unwrap());
+
+        // A checkpoint of another input is refused
+        let mut other = Input::SymlinkTarget(Cursor::new(data.clone()));
+        assert!(digest_checkpointed(&mut other, "other", &options, &resume).is_err());
+    }
+
+    #[test]
+    fn test_checkpointed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        std::fs::write(&path, vec![7; 10_000]).unwrap();
+        let options = ChecksumOptions {
+            pipeline: super::super::Pipeline {
+                buffer_size: 1000,
+                buffers: 1,
+            },
+            ..ChecksumOptions::default()
+        };
+        let checkpoint = CheckpointOptions {
+            path: dir.path().join("data.checkpoint"),
+            interval: 1 << 30,
+            resume: false,
+        };
+        let files = [path.clone()];
+        let expected = format!(
+            "{}  {}\n",
+            hex::encode(Sha256::digest([7; 10_000])),
+            path.display()
+        );
+
+        // Shorter than the interval, so no checkpoint is ever written
+        let mut writer = Vec::new();
+        process_checkpointed(&files, &mut writer, &options, &checkpoint).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), expected);
+        assert!(!checkpoint.path.exists());
+
+        // A checkpoint saved partway through is removed once done
+        let display = path.display().to_string();
+        let mut partial = Input::File {
+            path: path.clone(),
+            reader: BufReader::new(std::fs::File::open(&path).unwrap()),
+        };
+        let small = CheckpointOptions {
+            interval: 4000,
+            ..checkpoint.clone()
+        };
+        digest_checkpointed(&mut partial, &display, &options, &small).unwrap();
+        assert_eq!(load(&checkpoint.path).unwrap().offset, 8000);
+        let resume = CheckpointOptions {
+            resume: true,
+            ..checkpoint.clone()
+        };
+        let mut writer = Vec::new();
+        process_checkpointed(&files, &mut writer, &options, &resume).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), expected);
+        assert!(!checkpoint.path.exists());
+
+        // An input edited since the checkpoint is not resumed
+        let mut partial = Input::File {
+            path: path.clone(),
+            reader: BufReader::new(std::fs::File::open(&path).unwrap()),
+        };
+        digest_checkpointed(&mut partial, &display, &options, &small).unwrap();
+        std::fs::write(&path, vec![8; 10_001]).unwrap();
+        let error = process_checkpointed(&files, &mut Vec::new(), &options, &resume).unwrap_err();
+        assert!(error.to_string().contains("changed since"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_checkpoint_file_is_checked() {
+        let saved = Saved {
+            input: "\\a\\nb".to_string(),
+            offset: 42,
+            stamp: Some((100, 1_700_000_000_123_456_789)),
+            states: vec![(Algorithm::Crc32c, vec![1, 2, 3, 4])],
+        };
+        let contents = serialize(&saved);
+        assert_eq!(deserialize(&contents), Some(saved));
+        assert_eq!(deserialize(&contents.replace("42", "43")), None);
+        assert_eq!(deserialize(&contents[..contents.len() - 1]), None);
+    }
+}
@@ -0,0 +1,353 @@
+//! Comparison of two checksum manifests for `--manifest-diff`.
+//!
+//! Both manifests are read like `--check` reads them, in any layout and
+//! encoding it accepts, but no listed file is opened. A path only in the new
+//! manifest is added, one only in the old manifest is removed, and one in both
+//! is modified unless the digests of every algorithm both list for it agree.
+//! A removed and an added path with the same contents are reported as one
+//! rename instead.
+
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use super::Algorithm;
+use super::check::{parse_record, plural};
+use super::escape::display_filename;
+use crate::utils::{SilentFailure, Trouble};
+
+/// Options controlling how manifests are read and differences printed.
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Algorithm assumed for lines that don't name one.
+    pub algorithm: Algorithm,
+    /// Whether untagged lines are keyed digests.
+    pub keyed: bool,
+    /// Print one JSON record per difference.
+    pub json: bool,
+    /// Records end with NUL instead of a newline, and names are not escaped.
+    pub zero: bool,
+}
+
+/// One digest listed for a path: algorithm, whether it is keyed, and bytes.
+type Listed = (Algorithm, bool, Vec<u8>);
+
+/// Digests listed in a manifest, by path.
+pub type Manifest = BTreeMap<String, Vec<Listed>>;
+
+/// A difference between two manifests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Listed only in the new manifest.
+    Added(String),
+    /// Listed only in the old manifest.
+    Removed(String),
+    /// Listed in both with different contents.
+    Modified(String),
+    /// Removed from `from` and added as `to` with the same contents.
+    Renamed {
+        /// Path in the old manifest.
+        from: String,
+        /// Path in the new manifest.
+        to: String,
+    },
+}
+
+impl Change {
+    /// Returns the status word printed for this change.
+    fn status(&self) -> &'static str {
+        match self {
+            Change::Added(_) => "added",
+            Change::Removed(_) => "removed",
+            Change::Modified(_) => "modified",
+            Change::Renamed { .. } => "renamed",
+        }
+    }
+
+    /// Returns the path the change is sorted by: the old one for renames.
+    fn path(&self) -> &str {
+        match self {
+            Change::Added(path) | Change::Removed(path) | Change::Modified(path) => path,
+            Change::Renamed { from, .. } => from,
+        }
+    }
+}
+
+/// Prints the differences between the manifests `old` and `new`.
+///
+/// Like `diff`, fails silently after printing if anything changed, and with
+/// `Trouble` if the manifests can't be read or the output can't be written,
+/// so the exit status is 0, 1 or 2.
+pub fn process_diff<W: Write + ?Sized>(
+    old: &Path,
+    new: &Path,
+    writer: &mut W,
+    options: &DiffOptions,
+) -> Result<()> {
+    let mut compare = || -> Result<bool> {
+        let old = read_manifest(open(old)?, &old.display().to_string(), options)?;
+        let new = read_manifest(open(new)?, &new.display().to_string(), options)?;
+        let changes = diff_manifests(&old, &new);
+        for change in &changes {
+            write_change(&mut *writer, change, options)?;
+        }
+        Ok(!changes.is_empty())
+    };
+    if compare().map_err(Trouble)? {
+        return Err(SilentFailure.into());
+    }
+    Ok(())
+}
+
+fn open(path: &Path) -> Result<File> {
+    File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))
+}
+
+/// Reads every checksum line of a manifest.
+///
+/// Improperly formatted lines are skipped with a warning on stderr; a
+/// manifest that has lines but none of them valid is an error.
+pub fn read_manifest<R: Read>(
+    reader: R,
+    manifest_display: &str,
+    options: &DiffOptions,
+) -> Result<Manifest> {
+    let mut manifest = Manifest::new();
+    let mut reader = BufReader::new(reader);
+    let mut buffer = Vec::new();
+    let end = if options.zero { b'\0' } else { b'\n' };
+    let mut improperly_formatted = 0;
+
+    loop {
+        buffer.clear();
+        if reader.read_until(end, &mut buffer)? == 0 {
+            break;
+        }
+        if buffer.last() == Some(&end) {
+            buffer.pop();
+        }
+        let entry = std::str::from_utf8(&buffer)
+            .ok()
+            .and_then(|line| parse_record(line, options.algorithm, options.keyed, options.zero));
+        let Some(entry) = entry else {
+            improperly_formatted += 1;
+            continue;
+        };
+        let listed = manifest.entry(entry.path).or_default();
+        // A later line for the same path and algorithm replaces an earlier one
+        listed
+            .retain(|(algorithm, keyed, _)| (*algorithm, *keyed) != (entry.algorithm, entry.keyed));
+        listed.push((entry.algorithm, entry.keyed, entry.digest));
+    }
+
+    if improperly_formatted > 0 {
+        if manifest.is_empty() {
+            anyhow::bail!(
+                "{}: no properly formatted checksum lines found",
+                manifest_display
+            );
+        }
+        eprintln!(
+            "WARNING: {}: {}",
+            manifest_display,
+            plural(
+                improperly_formatted,
+                "line is improperly formatted",
+                "lines are improperly formatted"
+            )
+        );
+    }
+    Ok(manifest)
+}
+
+/// Returns whether two paths have the same contents: `Some(true)` if every
+/// digest both list agrees, `None` if they share no algorithm.
+fn same_contents(old: &[Listed], new: &[Listed]) -> Option<bool> {
+    let mut compared = false;
+    for (algorithm, keyed, digest) in old {
+        if let Some((_, _, other)) = new.iter().find(|(other_algorithm, other_keyed, _)| {
+            (other_algorithm, other_keyed) == (algorithm, keyed)
+        }) {
+            if digest != other {
+                return Some(false);
+            }
+            compared = true;
+        }
+    }
+    compared.then_some(true)
+}
+
+/// Returns the changes from `old` to `new`, sorted by path.
+///
+/// A path whose digests can't be compared, because the manifests list it
+/// with different algorithms, counts as modified. Each removed path is paired
+/// with the first added path of the same contents, in path order.
+pub fn diff_manifests(old: &Manifest, new: &Manifest) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut removed = Vec::new();
+    for (path, listed) in old {
+        match new.get(path) {
+            None => removed.push(path),
+            Some(other) => {
+                if same_contents(listed, other) != Some(true) {
+                    changes.push(Change::Modified(path.clone()));
+                }
+            }
+        }
+    }
+
+    let added: Vec<_> = new.keys().filter(|path| !old.contains_key(*path)).collect();
+    let mut by_digest: HashMap<(&str, bool, &[u8]), Vec<usize>> = HashMap::new();
+    for (index, path) in added.iter().enumerate() {
+        for (algorithm, keyed, digest) in &new[*path] {
+            by_digest
+                .entry((algorithm.name(), *keyed, digest))
+                .or_default()
+                .push(index);
+        }
+    }
+    let mut renamed = vec![false; added.len()];
+    for path in removed {
+        let listed = &old[path];
+        let target = listed
+            .iter()
+            .filter_map(|(algorithm, keyed, digest)| {
+                by_digest.get(&(algorithm.name(), *keyed, digest.as_slice()))
+            })
+            .flatten()
+            .copied()
+            .filter(|index| !renamed[*index])
+            .find(|index| same_contents(listed, &new[added[*index]]) == Some(true));
+        match target {
+            Some(index) => {
+                renamed[index] = true;
+                changes.push(Change::Renamed {
+                    from: path.clone(),
+                    to: added[index].clone(),
+                });
+            }
+            None => changes.push(Change::Removed(path.clone())),
+        }
+    }
+    for (index, path) in added.iter().enumerate() {
+        if !renamed[index] {
+            changes.push(Change::Added((*path).clone()));
+        }
+    }
+
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    changes
+}
+
+/// Prints one change as `<status>  <path>` (`<old> -> <new>` for renames),
+/// or as a JSON record.
+fn write_change<W: Write + ?Sized>(
+    writer: &mut W,
+    change: &Change,
+    options: &DiffOptions,
+) -> Result<()> {
+    if options.json {
+        let record = match change {
+            Change::Renamed { from, to } => serde_json::json!({
+                "status": change.status(),
+                "path": to,
+                "from": from,
+            }),
+            _ => serde_json::json!({
+                "status": change.status(),
+                "path": change.path(),
+            }),
+        };
+        writeln!(writer, "{}", record)?;
+        return Ok(());
+    }
+
+    let shown = |path: &str| {
+        if options.zero {
+            path.to_string()
+        } else {
+            display_filename(path)
+        }
+    };
+    let paths = match change {
+        Change::Renamed { from, to } => format!("{} -> {}", shown(from), shown(to)),
+        _ => shown(change.path()),
+    };
+    write!(writer, "{:<8}  {}", change.status(), paths)?;
+    writer.write_all(if options.zero { b"\0" } else { b"\n" })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn manifest(lines: &str) -> Manifest {
+        read_manifest(Cursor::new(lines), "-", &DiffOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_diff_manifests() {
+        let a = "a".repeat(64);
+        let b = "b".repeat(64);
+        let c = "c".repeat(64);
+        let old = manifest(&format!(
+            "{a}  same\n{b}  changed\n{c}  old name\n{a}  gone\n"
+        ));
+        let new = manifest(&format!(
+            "{a}  same\n{a}  changed\n{c}  new name\n{b}  fresh\n"
+        ));
+        assert_eq!(
+            diff_manifests(&old, &new),
+            [
+                Change::Modified("changed".to_string()),
+                Change::Added("fresh".to_string()),
+                Change::Removed("gone".to_string()),
+                Change::Renamed {
+                    from: "old name".to_string(),
+                    to: "new name".to_string(),
+                },
+            ]
+        );
+        assert!(diff_manifests(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_diff_algorithms() {
+        let sha256 = "a".repeat(64);
+        let sha512 = "a".repeat(128);
+        // Digests of an algorithm only one manifest lists are not compared
+        let old = manifest(&format!(
+            "{sha256}  f\nSHA512 (f) = {sha512}\nSHA512 (g) = {sha512}\n"
+        ));
+        let new = manifest(&format!("{sha256}  f\n{sha256}  g\n"));
+        assert_eq!(
+            diff_manifests(&old, &new),
+            [Change::Modified("g".to_string())]
+        );
+
+        let mut writer = Vec::new();
+        let options = DiffOptions {
+            json: true,
+            ..DiffOptions::default()
+        };
+        let renamed = Change::Renamed {
+            from: "a".to_string(),
+            to: "b".to_string(),
+        };
+        write_change(&mut writer, &renamed, &options).unwrap();
+        write_change(
+            &mut writer,
+            &Change::Added("x\ny".to_string()),
+            &DiffOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "{\"from\":\"a\",\"path\":\"b\",\"status\":\"renamed\"}\nadded     \\x\\ny\n"
+        );
+    }
+}
@@ -0,0 +1,493 @@
+//! Duplicate file detection for `--dupes`.
+//!
+//! Files are narrowed down in three rounds, each only looking at files that
+//! are still candidates: equal sizes, then equal digests of the first
+//! `PARTIAL_SIZE` bytes, then equal digests of the whole file. The digests
+//! come from the same hashers as `--checksum` (and its `--cache`).
+//!
+//! With `--link`, every other file in a set is replaced by a hard link or a
+//! reflink to the first one. Files are compared byte for byte first, so a
+//! hash collision can never lose data, and the replacement is created next
+//! to the duplicate and renamed over it.
+
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::escape::display_filename;
+use super::{ChecksumOptions, digest_cached, digest_reader};
+use crate::utils::{Input, InputOptions, SilentFailure, Source, sources};
+
+/// Number of leading bytes hashed to split files of equal size cheaply.
+pub const PARTIAL_SIZE: u64 = 16 * 1024;
+
+/// How `--link` replaces duplicates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LinkMode {
+    /// Hard link to the kept file; all copies then share metadata.
+    Hardlink,
+    /// Copy-on-write clone of the kept file (Linux, on btrfs, XFS, ...).
+    Reflink,
+}
+
+/// A file considered for deduplication.
+struct Candidate {
+    path: PathBuf,
+    size: u64,
+    #[cfg(unix)]
+    id: (u64, u64),
+}
+
+impl Candidate {
+    /// Returns what identifies the file on disk: its device and inode, or its
+    /// path where those are unavailable.
+    fn identity(&self) -> impl Hash + Eq + use<> {
+        #[cfg(unix)]
+        return self.id;
+        #[cfg(not(unix))]
+        return self.path.clone();
+    }
+
+    /// Returns `true` if both candidates are the same file on disk.
+    fn is_same_file(&self, other: &Candidate) -> bool {
+        self.identity() == other.identity()
+    }
+}
+
+/// A set of files with identical contents, in argument order.
+pub struct DuplicateSet {
+    /// Size of each file in bytes.
+    pub size: u64,
+    /// Full digests, one per algorithm.
+    pub digests: Vec<Vec<u8>>,
+    /// Paths of the files; the first one is kept by `--link`.
+    pub paths: Vec<PathBuf>,
+}
+
+/// Finds duplicates among `files` and prints one entry per set, then
+/// replaces duplicates if `link` is given.
+///
+/// Files that cannot be read are reported on stderr and left out; the
+/// command then fails after printing the sets it found.
+pub fn process_dupes<W: Write + ?Sized>(
+    files: &[PathBuf],
+    input_options: &InputOptions,
+    writer: &mut W,
+    options: &ChecksumOptions,
+    link: Option<LinkMode>,
+) -> Result<()> {
+    let mut failures = 0;
+    let sets = find_duplicates(files, input_options, options, &mut failures);
+    for (index, set) in sets.iter().enumerate() {
+        write_set(&mut *writer, set, options, index == 0)?;
+    }
+
+    if let Some(mode) = link {
+        let (mut replaced, mut saved) = (0, 0);
+        for set in &sets {
+            let kept = &set.paths[0];
+            for duplicate in &set.paths[1..] {
+                match replace(kept, duplicate, mode) {
+                    Ok(true) => {
+                        replaced += 1;
+                        saved += set.size;
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        eprintln!("{}: {:#}", duplicate.display(), e);
+                        failures += 1;
+                    }
+                }
+            }
+        }
+        eprintln!("Replaced {} duplicates, saving {} bytes", replaced, saved);
+    }
+
+    if failures > 0 {
+        return Err(SilentFailure.into());
+    }
+    Ok(())
+}
+
+/// Returns the sets of identical files among `files`, in order of their
+/// first file. Empty files are ignored, and files that can't be read are
+/// reported on stderr and counted in `failures`.
+///
+/// Like `fdupes`, hard links to a file and repeated paths count as that one
+/// file, so they are never reported and `--link` doesn't find them again.
+pub fn find_duplicates(
+    files: &[PathBuf],
+    input_options: &InputOptions,
+    options: &ChecksumOptions,
+    failures: &mut usize,
+) -> Vec<DuplicateSet> {
+    let mut candidates = Vec::new();
+    for source in sources(files, input_options) {
+        match source {
+            Ok(Source::File(path)) => match candidate(path) {
+                Ok(Some(candidate)) => candidates.push(candidate),
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("{:#}", e);
+                    *failures += 1;
+                }
+            },
+            Ok(Source::SymlinkTarget { .. }) => {}
+            Ok(Source::Skipped(message)) => eprintln!("{}", message),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                *failures += 1;
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    candidates.retain(|candidate| seen.insert(candidate.identity()));
+
+    let all: Vec<usize> = (0..candidates.len()).collect();
+    let mut groups = Vec::new();
+    for (size, group) in split_by(all, failures, |i| Ok(candidates[i].size)) {
+        for (partial, group) in
+            split_by(group, failures, |i| partial_digest(&candidates[i], options))
+        {
+            // The partial digest of a small file covers all of it
+            if size <= PARTIAL_SIZE {
+                groups.push((size, partial, group));
+                continue;
+            }
+            for (full, group) in split_by(group, failures, |i| full_digest(&candidates[i], options))
+            {
+                groups.push((size, full, group));
+            }
+        }
+    }
+    // Sets in order of their first file
+    groups.sort_by_key(|(_, _, group)| group[0]);
+    groups
+        .into_iter()
+        .map(|(size, digests, group)| DuplicateSet {
+            size,
+            digests,
+            paths: group.iter().map(|&i| candidates[i].path.clone()).collect(),
+        })
+        .collect()
+}
+
+/// Returns a candidate for a non-empty regular file at `path`.
+fn candidate(path: PathBuf) -> Result<Option<Candidate>> {
+    let metadata = std::fs::metadata(&path)
+        .with_context(|| format!("Failed to open file: {}", path.display()))?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Ok(None);
+    }
+    Ok(Some(Candidate {
+        size: metadata.len(),
+        #[cfg(unix)]
+        id: {
+            use std::os::unix::fs::MetadataExt;
+            (metadata.dev(), metadata.ino())
+        },
+        path,
+    }))
+}
+
+/// Splits `group` into groups of equal `key`, keeping their order and
+/// dropping groups of one. Files whose key can't be computed are reported
+/// and dropped.
+fn split_by<K: Clone + Eq + Hash>(
+    group: Vec<usize>,
+    failures: &mut usize,
+    mut key: impl FnMut(usize) -> Result<K>,
+) -> Vec<(K, Vec<usize>)> {
+    let mut groups: Vec<(K, Vec<usize>)> = Vec::new();
+    let mut index_of: HashMap<K, usize> = HashMap::new();
+    for i in group {
+        let key = match key(i) {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("{:#}", e);
+                *failures += 1;
+                continue;
+            }
+        };
+        let index = *index_of.entry(key.clone()).or_insert_with(|| {
+            groups.push((key, Vec::new()));
+            groups.len() - 1
+        });
+        groups[index].1.push(i);
+    }
+    groups.retain(|(_, group)| group.len() > 1);
+    groups
+}
+
+/// Hashes the first `PARTIAL_SIZE` bytes of a candidate.
+fn partial_digest(candidate: &Candidate, options: &ChecksumOptions) -> Result<Vec<Vec<u8>>> {
+    let file = open(&candidate.path)?;
+    let digests = digest_reader(
+        file.take(PARTIAL_SIZE),
+        &options.algorithms,
+        options.key.as_ref(),
+    )
+    .with_context(|| format!("Failed to read file: {}", candidate.path.display()))?;
+    Ok(digests)
+}
+
+/// Hashes a whole candidate, through the cache if there is one.
+fn full_digest(candidate: &Candidate, options: &ChecksumOptions) -> Result<Vec<Vec<u8>>> {
+    let file = open(&candidate.path)?;
+    let metadata = file.metadata()?;
+    let path_display = candidate.path.display().to_string();
+    let mut input = Input::File {
+        path: candidate.path.clone(),
+        reader: BufReader::new(file),
+    };
+    digest_cached(&mut input, Some(&metadata), &path_display, options)
+        .with_context(|| format!("Failed to read file: {}", path_display))
+}
+
+fn open(path: &Path) -> Result<File> {
+    File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))
+}
+
+/// Prints one set: its paths followed by a blank line between sets, like
+/// `fdupes`, or one JSON record.
+fn write_set<W: Write + ?Sized>(
+    writer: &mut W,
+    set: &DuplicateSet,
+    options: &ChecksumOptions,
+    first: bool,
+) -> Result<()> {
+    if options.json {
+        write!(writer, "{{\"size\":{}", set.size)?;
+        for (algorithm, digest) in options.algorithms.iter().zip(&set.digests) {
+            write!(
+                writer,
+                ",{}:{}",
+                serde_json::to_string(&options.label(*algorithm))?,
+                serde_json::to_string(&options.render(*algorithm, digest))?
+            )?;
+        }
+        let paths: Vec<_> = set
+            .paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        writeln!(writer, ",\"paths\":{}}}", serde_json::to_string(&paths)?)?;
+    } else {
+        if !first {
+            writeln!(writer)?;
+        }
+        for path in &set.paths {
+            writeln!(writer, "{}", display_filename(&path.display().to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces `duplicate` with a link to `kept` after checking that their
+/// contents are identical. Returns `false` if they already are the same file.
+fn replace(kept: &Path, duplicate: &Path, mode: LinkMode) -> Result<bool> {
+    let kept_candidate = candidate(kept.to_path_buf())?;
+    let duplicate_candidate = candidate(duplicate.to_path_buf())?;
+    let (Some(kept_candidate), Some(duplicate_candidate)) = (kept_candidate, duplicate_candidate)
+    else {
+        anyhow::bail!("File changed since it was hashed");
+    };
+    if kept_candidate.is_same_file(&duplicate_candidate) {
+        return Ok(false);
+    }
+    if !same_contents(kept, duplicate)? {
+        anyhow::bail!("File changed since it was hashed");
+    }
+
+    let dir = duplicate.parent().unwrap_or(Path::new("."));
+    let file_name = duplicate.file_name().unwrap_or_default().to_string_lossy();
+    let temp = dir.join(format!(".{}.{}.dupes", file_name, std::process::id()));
+    let result = match mode {
+        LinkMode::Hardlink => std::fs::hard_link(kept, &temp)
+            .with_context(|| format!("Failed to hard link to {}", kept.display())),
+        LinkMode::Reflink => reflink(kept, duplicate, &temp),
+    }
+    .and_then(|()| std::fs::rename(&temp, duplicate).map_err(anyhow::Error::from));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result.map(|()| true)
+}
+
+/// Returns `true` if both files have the same bytes.
+fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    let mut a = BufReader::new(open(a)?);
+    let mut b = BufReader::new(open(b)?);
+    let mut buffer_a = vec![0; 64 * 1024];
+    let mut buffer_b = vec![0; 64 * 1024];
+    loop {
+        let read = a.read(&mut buffer_a)?;
+        if read == 0 {
+            return Ok(b.read(&mut buffer_b[..1])? == 0);
+        }
+        if b.read_exact(&mut buffer_b[..read]).is_err() || buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Creates `temp` as a copy-on-write clone of `kept` with the permissions
+/// and ownership of `duplicate`. Fails if the ownership can't be kept, as
+/// when a file of another user is replaced without privileges.
+#[cfg(target_os = "linux")]
+fn reflink(kept: &Path, duplicate: &Path, temp: &Path) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let source = open(kept)?;
+    let target = File::options()
+        .write(true)
+        .create_new(true)
+        .open(temp)
+        .with_context(|| format!("Failed to create {}", temp.display()))?;
+    // SAFETY: both descriptors are open for the duration of the call, and
+    // FICLONE only reads its integer argument.
+    if unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to reflink to {}", kept.display()));
+    }
+    let metadata = std::fs::metadata(duplicate)?;
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::os::unix::fs::fchown(&target, Some(metadata.uid()), Some(metadata.gid()))
+            .with_context(|| format!("Failed to keep the owner of {}", duplicate.display()))?;
+    }
+    target.set_permissions(metadata.permissions())?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_kept: &Path, _duplicate: &Path, _temp: &Path) -> Result<()> {
+    anyhow::bail!("Reflinks are only supported on Linux")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arguments::checksum::Algorithm;
+
+    fn write(dir: &Path, name: &str, contents: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let big = vec![7; PARTIAL_SIZE as usize + 10];
+        let mut big_tail = big.clone();
+        *big_tail.last_mut().unwrap() = 8;
+        let files = vec![
+            write(dir.path(), "a", b"hello"),
+            write(dir.path(), "b", b"world"),
+            write(dir.path(), "c", b"hello"),
+            write(dir.path(), "big1", &big),
+            write(dir.path(), "big2", &big_tail),
+            write(dir.path(), "big3", &big),
+            write(dir.path(), "empty1", b""),
+            write(dir.path(), "empty2", b""),
+        ];
+
+        let options = Algorithm::Blake3.into();
+        let mut failures = 0;
+        let sets = find_duplicates(&files, &InputOptions::default(), &options, &mut failures);
+        assert_eq!(failures, 0);
+        let paths: Vec<_> = sets.iter().map(|set| set.paths.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                vec![files[0].clone(), files[2].clone()],
+                vec![files[3].clone(), files[5].clone()]
+            ]
+        );
+        assert_eq!(sets[1].size, big.len() as u64);
+        assert_eq!(sets[1].digests, [blake3::hash(&big).as_bytes().to_vec()]);
+    }
+
+    #[test]
+    fn test_dupes_output_and_hardlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = vec![
+            write(dir.path(), "a", b"hello"),
+            write(dir.path(), "b", b"hello"),
+        ];
+
+        let options = ChecksumOptions {
+            json: true,
+            ..ChecksumOptions::default()
+        };
+        let mut writer = Vec::new();
+        process_dupes(
+            &files,
+            &InputOptions::default(),
+            &mut writer,
+            &options,
+            Some(LinkMode::Hardlink),
+        )
+        .unwrap();
+        let record: serde_json::Value = serde_json::from_slice(&writer).unwrap();
+        assert_eq!(
+            record,
+            serde_json::json!({
+                "size": 5,
+                "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+                "paths": [files[0].display().to_string(), files[1].display().to_string()],
+            })
+        );
+
+        // Linked files are no longer reported as needing replacement
+        assert_eq!(std::fs::read(&files[1]).unwrap(), b"hello");
+        assert!(!replace(&files[0], &files[1], LinkMode::Hardlink).unwrap());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(std::fs::metadata(&files[0]).unwrap().nlink(), 2);
+        }
+    }
+
+    #[test]
+    fn test_same_file_is_not_a_duplicate() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = write(dir.path(), "a", b"hello");
+        let c = write(dir.path(), "c", b"hello");
+        let mut files = vec![a.clone(), a.clone()];
+        #[cfg(unix)]
+        {
+            let b = dir.path().join("b");
+            std::fs::hard_link(&a, &b).unwrap();
+            files.push(b);
+        }
+
+        // A repeated path or a hard link is the same file, not a duplicate
+        let options = ChecksumOptions::default();
+        let mut failures = 0;
+        let sets = find_duplicates(&files, &InputOptions::default(), &options, &mut failures);
+        assert!(sets.is_empty());
+
+        // A real copy is reported once, with the first path of the file
+        files.push(c.clone());
+        let sets = find_duplicates(&files, &InputOptions::default(), &options, &mut failures);
+        let paths: Vec<_> = sets.iter().map(|set| set.paths.clone()).collect();
+        assert_eq!(paths, [vec![a, c]]);
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn test_replace_refuses_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = write(dir.path(), "a", b"hello");
+        let b = write(dir.path(), "b", b"hellO");
+        assert!(replace(&a, &b, LinkMode::Hardlink).is_err());
+        assert_eq!(std::fs::read(&b).unwrap(), b"hellO");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
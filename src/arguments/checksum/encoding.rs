@@ -0,0 +1,164 @@
+//! Text encodings of digests and Subresource Integrity (SRI) strings.
+//!
+//! Digests are printed in hex by default, or in one of the RFC 4648 encodings
+//! with `--encoding`, and `--check` recognizes any of them without being told
+//! which one a manifest uses. Text made of hex digits is always read as hex:
+//! a hex digest of another length can also be valid base64 of the expected
+//! length (64 hex digits decode to the 48 bytes of a SHA-384), and is refused
+//! rather than read as a different digest.
+
+use data_encoding::{BASE32, BASE32_NOPAD, BASE64, BASE64_NOPAD, BASE64URL, BASE64URL_NOPAD};
+
+use super::Algorithm;
+
+/// Text encoding of a digest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    /// Lowercase hexadecimal.
+    #[default]
+    Hex,
+    /// Standard base64 with padding.
+    Base64,
+    /// URL-safe base64 without padding.
+    Base64url,
+    /// Uppercase base32 with padding.
+    Base32,
+}
+
+impl Encoding {
+    /// Encodes raw digest bytes.
+    pub fn encode(self, digest: &[u8]) -> String {
+        match self {
+            Encoding::Hex => hex::encode(digest),
+            Encoding::Base64 => BASE64.encode(digest),
+            Encoding::Base64url => BASE64URL_NOPAD.encode(digest),
+            Encoding::Base32 => BASE32.encode(digest),
+        }
+    }
+}
+
+/// Decodes a digest of `len` bytes written in any supported encoding, with or
+/// without padding. Returns `None` if the text is hex of another length, or
+/// if not exactly one encoding yields `len` bytes.
+pub fn decode(text: &str, len: usize) -> Option<Vec<u8>> {
+    if let Ok(digest) = hex::decode(text) {
+        return (digest.len() == len).then_some(digest);
+    }
+    let encodings: [&[_]; 2] = [
+        &[BASE64, BASE64_NOPAD, BASE64URL, BASE64URL_NOPAD],
+        &[BASE32, BASE32_NOPAD],
+    ];
+    let mut found = None;
+    for decoders in encodings {
+        let Some(digest) = decoders
+            .iter()
+            .filter_map(|decoder| decoder.decode(text.as_bytes()).ok())
+            .find(|digest| digest.len() == len)
+        else {
+            continue;
+        };
+        if found.replace(digest).is_some() {
+            return None;
+        }
+    }
+    found
+}
+
+/// Returns the SRI name of `algorithm`, if browsers accept it in `integrity`.
+pub fn sri_name(algorithm: Algorithm) -> Option<&'static str> {
+    match algorithm {
+        Algorithm::Sha256 => Some("sha256"),
+        Algorithm::Sha384 => Some("sha384"),
+        Algorithm::Sha512 => Some("sha512"),
+        _ => None,
+    }
+}
+
+/// Renders `digest` as an SRI hash expression like `sha384-<base64>`.
+///
+/// # Panics
+///
+/// Panics if `algorithm` has no `sri_name`.
+pub fn sri(algorithm: Algorithm, digest: &[u8]) -> String {
+    let name = sri_name(algorithm).expect("algorithm is valid for SRI");
+    format!("{}-{}", name, BASE64.encode(digest))
+}
+
+/// Parses an SRI `integrity` value of one or more space-separated hash
+/// expressions and returns the strongest one, as a browser would.
+///
+/// Returns `None` if any expression is not a valid SRI hash.
+pub fn parse_sri(text: &str) -> Option<(Algorithm, Vec<u8>)> {
+    let mut strongest: Option<(Algorithm, Vec<u8>)> = None;
+    for expression in text.split(' ') {
+        let (name, encoded) = expression.split_once('-')?;
+        let algorithm = [Algorithm::Sha256, Algorithm::Sha384, Algorithm::Sha512]
+            .into_iter()
+            .find(|algorithm| sri_name(*algorithm) == Some(name))?;
+        // Options such as `?ct=...` may follow the digest
+        let encoded = encoded
+            .split_once('?')
+            .map_or(encoded, |(encoded, _)| encoded);
+        let digest = BASE64.decode(encoded.as_bytes()).ok()?;
+        if digest.len() != algorithm.digest_len() {
+            return None;
+        }
+        if strongest
+            .as_ref()
+            .is_none_or(|(current, _)| current.digest_len() < algorithm.digest_len())
+        {
+            strongest = Some((algorithm, digest));
+        }
+    }
+    strongest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_encoding_decodes() {
+        for len in [4, 8, 16, 20, 28, 32, 48, 64] {
+            let digest: Vec<u8> = (0..len as u8).map(|b| b.wrapping_mul(37)).collect();
+            for encoding in <Encoding as clap::ValueEnum>::value_variants() {
+                let text = encoding.encode(&digest);
+                // No other encoding decodes to a different digest of this length
+                assert_eq!(decode(&text, len), Some(digest.clone()), "{:?}", encoding);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hex_of_another_length_is_refused() {
+        // A SHA-256 in hex is also valid base64 for a 48-byte SHA-384
+        let sha256 = hex::encode([0xab; 32]);
+        assert_eq!(BASE64.decode(sha256.as_bytes()).unwrap().len(), 48);
+        assert_eq!(decode(&sha256, 48), None);
+        assert_eq!(decode(&sha256, 32), Some(vec![0xab; 32]));
+    }
+
+    #[test]
+    fn test_sri() {
+        // `echo -n hello | openssl dgst -sha384 -binary | openssl base64 -A`
+        let digest = hex::decode(
+            "59e1748777448c69de6b800d7a33bbfb9ff1b463e44354c3553bcdb9c666fa90125a3c79f90397bdf5f6a13de828684f",
+        )
+        .unwrap();
+        let expression = sri(Algorithm::Sha384, &digest);
+        assert_eq!(
+            expression,
+            "sha384-WeF0h3dEjGnea4ANejO7+5/xtGPkQ1TDVTvNucZm+pASWjx5+QOXvfX2oT3oKGhP"
+        );
+        assert_eq!(
+            parse_sri(&expression),
+            Some((Algorithm::Sha384, digest.clone()))
+        );
+
+        let sha256 = sri(Algorithm::Sha256, &[0; 32]);
+        let both = format!("{} {}", sha256, expression);
+        assert_eq!(parse_sri(&both), Some((Algorithm::Sha384, digest)));
+        assert_eq!(parse_sri("md5-XUFAKrxLKna5cZ2REBfFkg=="), None);
+        assert_eq!(parse_sri(&hex::encode([0; 32])), None);
+    }
+}
@@ -0,0 +1,71 @@
+//! GNU coreutils escaping of file names in checksum lines.
+//!
+//! A name containing a backslash, newline or carriage return would make its
+//! line ambiguous, so such lines start with a `\` and the name is written with
+//! `\\`, `\n` and `\r` escapes, like `sha256sum` does. Names are written raw
+//! with `--zero`, where records end with NUL instead.
+
+/// Returns `name` escaped, or `None` if it can be written as is.
+pub fn escape_filename(name: &str) -> Option<String> {
+    if !name.contains(['\\', '\n', '\r']) {
+        return None;
+    }
+    let mut escaped = String::with_capacity(name.len() + 2);
+    for c in name.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    Some(escaped)
+}
+
+/// Reverses `escape_filename`. Returns `None` on an unknown or truncated
+/// escape.
+pub fn unescape_filename(escaped: &str) -> Option<String> {
+    let mut name = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            name.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => name.push('\\'),
+            'n' => name.push('\n'),
+            'r' => name.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(name)
+}
+
+/// Returns `name` as shown in `--check` status lines: escaped and prefixed
+/// with `\` if needed.
+pub fn display_filename(name: &str) -> String {
+    match escape_filename(name) {
+        Some(escaped) => format!("\\{}", escaped),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_roundtrip() {
+        assert_eq!(escape_filename("plain name.txt"), None);
+        for name in ["a\\b", "line\nbreak", "cr\r\n", "\\n literally"] {
+            let escaped = escape_filename(name).unwrap();
+            assert!(!escaped.contains(['\n', '\r']));
+            assert_eq!(unescape_filename(&escaped).as_deref(), Some(name));
+        }
+        assert_eq!(escape_filename("a\\b\nc").unwrap(), "a\\\\b\\nc");
+        assert_eq!(unescape_filename("bad\\t"), None);
+        assert_eq!(unescape_filename("trailing\\"), None);
+        assert_eq!(display_filename("a\nb"), "\\a\\nb");
+    }
+}
@@ -0,0 +1,164 @@
+//! Secret keys for keyed checksums (HMAC and keyed BLAKE3).
+//!
+//! Keys are only ever read from a file, an inherited file descriptor or an
+//! environment variable, so they never show up in the process list or shell
+//! history. Error messages name the source of a key but never its contents,
+//! and the bytes are wiped from memory when the key is dropped.
+
+use anyhow::{Context, Result};
+use std::io::{self, Read};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Length of a keyed BLAKE3 key.
+pub const BLAKE3_KEY_LEN: usize = blake3::KEY_LEN;
+
+/// Secret key bytes. `Debug` never prints them.
+#[derive(Clone)]
+pub struct SecretKey(Zeroizing<Vec<u8>>);
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey(<redacted>)")
+    }
+}
+
+impl SecretKey {
+    /// Wraps raw key bytes, which must not be empty.
+    pub fn new(bytes: Vec<u8>) -> Result<Self> {
+        let bytes = Zeroizing::new(bytes);
+        if bytes.is_empty() {
+            anyhow::bail!("Key is empty");
+        }
+        Ok(Self(bytes))
+    }
+
+    /// Reads a key from a file. A single trailing newline is not part of the key.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open key file: {}", path.display()))?;
+        let bytes = read_key(file)
+            .with_context(|| format!("Failed to read key file: {}", path.display()))?;
+        Self::new(bytes).with_context(|| format!("Invalid key file: {}", path.display()))
+    }
+
+    /// Reads a key from an inherited file descriptor, e.g. `--key-fd 3 3<key`.
+    /// A single trailing newline is not part of the key.
+    ///
+    /// The descriptor is left open. It is duplicated first, so a number that
+    /// isn't an open descriptor is a normal error rather than undefined
+    /// behavior.
+    #[cfg(unix)]
+    pub fn from_fd(fd: std::os::fd::RawFd) -> Result<Self> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        if fd < 0 {
+            anyhow::bail!("Invalid key file descriptor {}", fd);
+        }
+        // SAFETY: F_DUPFD_CLOEXEC takes any integer, fails with EBADF if it
+        // is not an open descriptor, and has no other effect on it.
+        let duplicate = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if duplicate < 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() == Some(libc::EBADF) {
+                anyhow::bail!("Key file descriptor {} is not open", fd);
+            }
+            return Err(error)
+                .with_context(|| format!("Failed to open key file descriptor {}", fd));
+        }
+        // SAFETY: `duplicate` was just created and is owned by nothing else.
+        let owned = unsafe { OwnedFd::from_raw_fd(duplicate) };
+        let bytes = read_key(std::fs::File::from(owned))
+            .with_context(|| format!("Failed to read key file descriptor {}", fd))?;
+        Self::new(bytes).with_context(|| format!("Invalid key in file descriptor {}", fd))
+    }
+
+    /// Reads a key from the environment variable `name`, byte for byte.
+    pub fn from_env(name: &str) -> Result<Self> {
+        let value = std::env::var_os(name)
+            .with_context(|| format!("Key environment variable {} is not set", name))?;
+        Self::new(value.into_encoded_bytes())
+            .with_context(|| format!("Invalid key in environment variable {}", name))
+    }
+
+    /// Returns the raw key bytes.
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the key as a keyed BLAKE3 key, which must be exactly
+    /// `BLAKE3_KEY_LEN` bytes long.
+    pub fn blake3_key(&self) -> io::Result<[u8; BLAKE3_KEY_LEN]> {
+        self.bytes().try_into().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "keyed BLAKE3 needs a key of exactly {} bytes, got {}",
+                    BLAKE3_KEY_LEN,
+                    self.bytes().len()
+                ),
+            )
+        })
+    }
+}
+
+/// Reads all of `reader` and drops one trailing `\n` or `\r\n`.
+fn read_key<R: Read>(mut reader: R) -> io::Result<Vec<u8>> {
+    let mut bytes = Zeroizing::new(Vec::new());
+    reader.read_to_end(&mut bytes)?;
+    if bytes.last() == Some(&b'\n') {
+        bytes.pop();
+        if bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+    }
+    Ok(std::mem::take(&mut *bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_from_file_strips_one_newline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key");
+        std::fs::write(&path, "secret\n\n").unwrap();
+        assert_eq!(SecretKey::from_file(&path).unwrap().bytes(), b"secret\n");
+    }
+
+    #[test]
+    fn test_key_errors_do_not_leak_the_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key");
+        std::fs::write(&path, "\n").unwrap();
+        let error = SecretKey::from_file(&path).unwrap_err();
+        assert!(format!("{:#}", error).contains("Key is empty"));
+
+        let key = SecretKey::new(b"hunter2".to_vec()).unwrap();
+        assert!(!format!("{:?}", key).contains("hunter2"));
+        let error = key.blake3_key().unwrap_err();
+        assert!(!error.to_string().contains("hunter2"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_from_fd() {
+        use std::os::fd::AsRawFd;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key");
+        std::fs::write(&path, "secret\n").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        assert_eq!(
+            SecretKey::from_fd(file.as_raw_fd()).unwrap().bytes(),
+            b"secret"
+        );
+
+        // Descriptors that can't be open are errors, not undefined behavior
+        let error = SecretKey::from_fd(-1).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid key file descriptor -1");
+        let error = SecretKey::from_fd(i32::MAX).unwrap_err();
+        assert!(error.to_string().contains("is not open"), "{:#}", error);
+    }
+}
@@ -0,0 +1,256 @@
+//! Byte-range hashing for `--offset`, `--length` and `--chunk-size`.
+//!
+//! Each input is positioned at the offset by seeking where the input allows
+//! it and by reading and discarding bytes otherwise, as for stdin or a pipe.
+//! Digests of a range are printed like whole-file digests, with the path
+//! written as `<path>@<offset>+<length>` so they can't be mistaken for one;
+//! `--json` records get `offset` and `length` fields instead.
+
+use anyhow::Result;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::{ChecksumOptions, FileStat, digest_pipelined};
+use crate::utils::Input;
+
+/// Which bytes of each input are hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RangeOptions {
+    /// Bytes skipped at the start of each input.
+    pub offset: u64,
+    /// Bytes hashed after the offset; the rest of the input if `None`.
+    pub length: Option<u64>,
+    /// Print one digest per block of this many bytes instead of one for the
+    /// whole range.
+    pub chunk_size: Option<u64>,
+}
+
+/// Moves `input` forward to `offset`, or to its end if that comes first.
+/// Returns the position reached.
+pub fn skip_to(input: &mut Input, offset: u64) -> io::Result<u64> {
+    if offset == 0 {
+        return Ok(0);
+    }
+    let end = match input {
+        Input::File { reader, .. } => match reader.seek(SeekFrom::Start(offset)) {
+            Ok(_) => Some(reader.get_ref().metadata()?.len()),
+            Err(_) => None,
+        },
+        Input::SymlinkTarget(cursor) => {
+            cursor.set_position(offset);
+            Some(cursor.get_ref().len() as u64)
+        }
+        Input::Stdin(_) => None,
+    };
+    match end {
+        Some(end) => Ok(offset.min(end)),
+        // Pipes, terminals and stdin can't seek
+        None => io::copy(&mut input.take(offset), &mut io::sink()),
+    }
+}
+
+/// Hashes the range of `input` selected by `range` and prints one record,
+/// or one per chunk with `chunk_size`.
+pub fn process_range_input<W: Write>(
+    input: &mut Input,
+    path_display: &str,
+    mut writer: W,
+    options: &ChecksumOptions,
+    range: RangeOptions,
+    stat: FileStat,
+) -> Result<()> {
+    let mut offset = skip_to(input, range.offset)?;
+    let mut remaining = range.length.unwrap_or(u64::MAX);
+    let Some(chunk_size) = range.chunk_size else {
+        let (digests, length) = digest_range(input, remaining, options)?;
+        return write_range_record(
+            &mut writer,
+            &digests,
+            path_display,
+            offset,
+            length,
+            stat,
+            options,
+        );
+    };
+    while remaining > 0 {
+        let (digests, length) = digest_range(input, chunk_size.min(remaining), options)?;
+        if length == 0 {
+            break;
+        }
+        write_range_record(
+            &mut writer,
+            &digests,
+            path_display,
+            offset,
+            length,
+            stat,
+            options,
+        )?;
+        if length < chunk_size.min(remaining) {
+            break;
+        }
+        offset += length;
+        remaining -= length;
+    }
+    Ok(())
+}
+
+/// Hashes at most `limit` bytes of `input`. Returns the digests and the
+/// number of bytes hashed.
+fn digest_range(
+    input: &mut Input,
+    limit: u64,
+    options: &ChecksumOptions,
+) -> io::Result<(Vec<Vec<u8>>, u64)> {
+    let mut reader = input.take(limit);
+    let digests = digest_pipelined(
+        &mut reader,
+        &options.algorithms,
+        options.key.as_ref(),
+        options.pipeline,
+    )?;
+    Ok((digests, limit - reader.limit()))
+}
+
+/// Prints the record of one range; `stat.size` becomes its length.
+pub fn write_range_record<W: Write + ?Sized>(
+    writer: &mut W,
+    digests: &[Vec<u8>],
+    path_display: &str,
+    offset: u64,
+    length: u64,
+    stat: FileStat,
+    options: &ChecksumOptions,
+) -> Result<()> {
+    if !options.json {
+        let stat = FileStat {
+            size: Some(length),
+            ..stat
+        };
+        let shown = format!("{}@{}+{}", path_display, offset, length);
+        options.write_record(&mut *writer, digests, &shown, stat)?;
+        return Ok(());
+    }
+    write!(
+        writer,
+        "{{\"path\":{},\"offset\":{},\"length\":{}",
+        serde_json::to_string(path_display)?,
+        offset,
+        length
+    )?;
+    for (algorithm, digest) in options.algorithms.iter().zip(digests) {
+        write!(
+            writer,
+            ",{}:{}",
+            serde_json::to_string(&options.label(*algorithm))?,
+            serde_json::to_string(&options.render(*algorithm, digest))?
+        )?;
+    }
+    writeln!(writer, "}}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arguments::checksum::Algorithm;
+    use std::io::{BufReader, Cursor};
+
+    fn run(input: &mut Input, range: RangeOptions) -> String {
+        let options = ChecksumOptions {
+            algorithms: vec![Algorithm::Crc32c],
+            ..ChecksumOptions::default()
+        };
+        let mut writer = Vec::new();
+        process_range_input(
+            input,
+            "f",
+            &mut writer,
+            &options,
+            range,
+            FileStat::default(),
+        )
+        .unwrap();
+        String::from_utf8(writer).unwrap()
+    }
+
+    #[test]
+    fn test_range_offset_and_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f");
+        std::fs::write(&path, "hello world").unwrap();
+        let range = RangeOptions {
+            offset: 6,
+            length: Some(3),
+            chunk_size: None,
+        };
+        let mut file = Input::File {
+            path: path.clone(),
+            reader: BufReader::new(std::fs::File::open(&path).unwrap()),
+        };
+        let expected = format!("crc32c:{}  f@6+3\n", crc32c_hex(b"wor"));
+        assert_eq!(run(&mut file, range), expected);
+        let mut cursor = Input::SymlinkTarget(Cursor::new(b"hello world".to_vec()));
+        assert_eq!(run(&mut cursor, range), expected);
+
+        // Past the end hashes nothing, at the end
+        let mut cursor = Input::SymlinkTarget(Cursor::new(b"hello".to_vec()));
+        let range = RangeOptions { offset: 9, ..range };
+        let expected = format!("crc32c:{}  f@5+0\n", crc32c_hex(b""));
+        assert_eq!(run(&mut cursor, range), expected);
+        std::fs::write(&path, "hello").unwrap();
+        let mut file = Input::File {
+            path: path.clone(),
+            reader: BufReader::new(std::fs::File::open(&path).unwrap()),
+        };
+        assert_eq!(run(&mut file, range), expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_range_offset_past_end_of_pipe() {
+        use std::os::fd::OwnedFd;
+
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        writer.write_all(b"hello").unwrap();
+        drop(writer);
+        let mut pipe = Input::File {
+            path: "pipe".into(),
+            reader: BufReader::new(std::fs::File::from(OwnedFd::from(reader))),
+        };
+        let range = RangeOptions {
+            offset: 9,
+            length: None,
+            chunk_size: None,
+        };
+        assert_eq!(
+            run(&mut pipe, range),
+            format!("crc32c:{}  f@5+0\n", crc32c_hex(b""))
+        );
+    }
+
+    #[test]
+    fn test_range_chunks() {
+        let mut input = Input::SymlinkTarget(Cursor::new(b"hello world".to_vec()));
+        let range = RangeOptions {
+            offset: 1,
+            length: Some(8),
+            chunk_size: Some(3),
+        };
+        assert_eq!(
+            run(&mut input, range),
+            format!(
+                "crc32c:{}  f@1+3\ncrc32c:{}  f@4+3\ncrc32c:{}  f@7+2\n",
+                crc32c_hex(b"ell"),
+                crc32c_hex(b"o w"),
+                crc32c_hex(b"or")
+            )
+        );
+        let mut empty = Input::SymlinkTarget(Cursor::new(Vec::new()));
+        assert_eq!(run(&mut empty, range), "");
+    }
+
+    fn crc32c_hex(data: &[u8]) -> String {
+        hex::encode(crc32c::crc32c(data).to_be_bytes())
+    }
+}
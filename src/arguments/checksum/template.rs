@@ -0,0 +1,285 @@
+//! `--format` templates for checksum lines.
+//!
+//! A template is literal text with `{field}` placeholders, e.g.
+//! `{algo}:{hash} {size} {path}`; `{{` and `}}` stand for literal braces.
+//! Templates are parsed by clap, so a typo is reported before any file is
+//! read.
+
+use std::fmt::Write as _;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Names accepted inside `{...}`, for error messages.
+const FIELD_NAMES: &str = "algo, hash, size, mtime, path";
+
+/// A value substituted into a template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// Algorithm name, e.g. `sha256`.
+    Algo,
+    /// Encoded digest.
+    Hash,
+    /// Size in bytes.
+    Size,
+    /// Modification time in UTC, as RFC 3339.
+    Mtime,
+    /// Path as given, or relative to `--relative-to`.
+    Path,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+/// A parsed `--format` template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<Part>);
+
+/// Size and modification time of an input, where known.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileStat {
+    /// Size in bytes.
+    pub size: Option<u64>,
+    /// Last modification time.
+    pub mtime: Option<SystemTime>,
+}
+
+impl From<&std::fs::Metadata> for FileStat {
+    fn from(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            size: Some(metadata.len()),
+            mtime: metadata.modified().ok(),
+        }
+    }
+}
+
+/// Values for one rendered line.
+pub struct Values<'a> {
+    /// Algorithm name.
+    pub algo: &'a str,
+    /// Encoded digest.
+    pub hash: &'a str,
+    /// Size and modification time of the input.
+    pub stat: FileStat,
+    /// Path to print.
+    pub path: &'a str,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let (name, rest) = chars
+                        .as_str()
+                        .split_once('}')
+                        .ok_or_else(|| format!("unclosed '{{' in template {:?}", text))?;
+                    let field = match name {
+                        "algo" => Field::Algo,
+                        "hash" => Field::Hash,
+                        "size" => Field::Size,
+                        "mtime" => Field::Mtime,
+                        "path" => Field::Path,
+                        _ => {
+                            return Err(format!(
+                                "unknown field {{{}}}; expected one of {}",
+                                name, FIELD_NAMES
+                            ));
+                        }
+                    };
+                    chars = rest.chars();
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => return Err("unmatched '}'; write '}}' for a literal brace".to_string()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        if !parts.contains(&Part::Field(Field::Hash)) {
+            return Err("template has no {hash} field".to_string());
+        }
+        Ok(Template(parts))
+    }
+}
+
+impl Template {
+    /// Returns `true` if rendering needs the size or modification time.
+    pub fn uses_stat(&self) -> bool {
+        self.0
+            .iter()
+            .any(|part| matches!(part, Part::Field(Field::Size | Field::Mtime)))
+    }
+
+    /// Renders one line without the trailing newline. Unknown sizes and
+    /// times are printed as `-`.
+    pub fn render(&self, values: &Values) -> String {
+        let mut line = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Literal(text) => line.push_str(text),
+                Part::Field(Field::Algo) => line.push_str(values.algo),
+                Part::Field(Field::Hash) => line.push_str(values.hash),
+                Part::Field(Field::Size) => match values.stat.size {
+                    Some(size) => write!(line, "{}", size).unwrap(),
+                    None => line.push('-'),
+                },
+                Part::Field(Field::Mtime) => match values.stat.mtime {
+                    Some(mtime) => line.push_str(&rfc3339(mtime)),
+                    None => line.push('-'),
+                },
+                Part::Field(Field::Path) => line.push_str(values.path),
+            }
+        }
+        line
+    }
+}
+
+/// Formats `time` as `YYYY-MM-DDTHH:MM:SSZ`, truncated to whole seconds.
+fn rfc3339(time: SystemTime) -> String {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs_f64().ceil() as i64),
+    };
+    let (days, seconds) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // Civil date from days since 1970-01-01, after Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Returns `path` relative to the directory `base`, going up with `..` where
+/// needed. Both are made absolute first; symbolic links are not resolved.
+pub fn relative_path(path: &Path, base: &Path) -> std::io::Result<PathBuf> {
+    let path = normalize(&std::path::absolute(path)?);
+    let base = normalize(&std::path::absolute(base)?);
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative: PathBuf = base
+        .components()
+        .skip(common)
+        .map(|_| Component::ParentDir)
+        .collect();
+    relative.extend(path.components().skip(common));
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Ok(relative)
+}
+
+/// Removes `.` and resolves `..` lexically in an absolute path.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_template_render() {
+        let template: Template = "{algo}:{hash} {size} {mtime} {{{path}}}".parse().unwrap();
+        let values = Values {
+            algo: "sha256",
+            hash: "abc",
+            stat: FileStat {
+                size: Some(5),
+                mtime: Some(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            },
+            path: "a b.txt",
+        };
+        assert!(template.uses_stat());
+        assert_eq!(
+            template.render(&values),
+            "sha256:abc 5 2000-02-29T00:00:00Z {a b.txt}"
+        );
+
+        let values = Values {
+            stat: FileStat::default(),
+            ..values
+        };
+        assert_eq!(template.render(&values), "sha256:abc - - {a b.txt}");
+    }
+
+    #[test]
+    fn test_template_errors() {
+        assert!(
+            "{hash} {sise}"
+                .parse::<Template>()
+                .unwrap_err()
+                .contains("{sise}")
+        );
+        assert!("{hash".parse::<Template>().is_err());
+        assert!("{hash} }".parse::<Template>().is_err());
+        assert!("{path}".parse::<Template>().is_err());
+        assert!(!"{hash}  {path}".parse::<Template>().unwrap().uses_stat());
+    }
+
+    #[test]
+    fn test_rfc3339_and_relative_path() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            "2023-11-14T22:13:20Z"
+        );
+        assert_eq!(
+            rfc3339(UNIX_EPOCH - Duration::from_secs(1)),
+            "1969-12-31T23:59:59Z"
+        );
+
+        let relative = |path, base| relative_path(Path::new(path), Path::new(base)).unwrap();
+        assert_eq!(relative("/a/b/c.txt", "/a"), Path::new("b/c.txt"));
+        assert_eq!(relative("/a/b/c.txt", "/a/d/"), Path::new("../b/c.txt"));
+        assert_eq!(relative("/a/./b/../c", "/a"), Path::new("c"));
+        assert_eq!(relative("/a", "/a"), Path::new("."));
+    }
+}
@@ -0,0 +1,327 @@
+//! Merkle-style digests of whole directory trees.
+//!
+//! The digest only depends on names, contents, the executable bit and the
+//! shape of the tree, so it is stable across machines, filesystems and
+//! checkouts. With algorithm `H`:
+//!
+//! * a regular file hashes to `H(contents)`, exactly like `--checksum`;
+//! * a symlink hashed with `SymlinkMode::Target` hashes to `H(target path)`;
+//! * a directory hashes to `H("tree\0" || entry...)`, where the entries are
+//!   sorted by the bytes of their names and each one is encoded as
+//!   `kind || u64_be(name length) || name || child digest`.
+//!
+//! `kind` is `f` for a regular file, `x` for an executable file, `d` for a
+//! directory and `l` for a symlink. Other file types are ignored.
+//!
+//! The digest printed for the root is `H("root\0" || kind || root digest)`,
+//! so it can't be mistaken for the digest of a file or directory inside a
+//! tree. With several algorithms, each one gets its own tree of digests from
+//! one walk.
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs::{self, File, Metadata};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use super::{Algorithm, MultiHasher, Pipeline, SecretKey, digest_input};
+use crate::utils::{Input, InputOptions, SymlinkMode};
+
+/// Prefix hashed before the entries of every directory.
+const TREE_PREFIX: &[u8] = b"tree\0";
+
+/// Prefix hashed before the kind and digest of the root.
+const ROOT_PREFIX: &[u8] = b"root\0";
+
+/// Returns the tree digest of `path` for each algorithm, keyed with `key` if
+/// given, reading files through `pipeline`. `path` may be a directory or a
+/// file.
+///
+/// Only `options.symlinks` and `options.one_file_system` are used; links
+/// given as `path` itself are always followed.
+pub fn digest_tree(
+    path: &Path,
+    algorithms: &[Algorithm],
+    key: Option<&SecretKey>,
+    pipeline: Pipeline,
+    options: &InputOptions,
+) -> Result<Vec<Vec<u8>>> {
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("Failed to read metadata: {}", path.display()))?;
+    let mut walker = TreeWalker {
+        algorithms,
+        key,
+        pipeline,
+        options,
+        root_device: device_id(&metadata),
+        ancestors: HashSet::new(),
+    };
+    let (kind, digests) = if metadata.is_dir() {
+        (b'd', walker.digest_directory(path)?)
+    } else {
+        (file_kind(&metadata), walker.digest_file(path)?)
+    };
+
+    let mut roots = Vec::with_capacity(digests.len());
+    for (digest, algorithm) in digests.iter().zip(algorithms) {
+        let mut hasher = MultiHasher::new(&[*algorithm], key)?;
+        hasher.update(ROOT_PREFIX);
+        hasher.update(&[kind]);
+        hasher.update(digest);
+        roots.append(&mut hasher.finalize());
+    }
+    Ok(roots)
+}
+
+struct TreeWalker<'a> {
+    algorithms: &'a [Algorithm],
+    key: Option<&'a SecretKey>,
+    pipeline: Pipeline,
+    options: &'a InputOptions,
+    root_device: Option<u64>,
+    /// Canonical paths of the directories being walked, to break symlink loops.
+    ancestors: HashSet<PathBuf>,
+}
+
+impl TreeWalker<'_> {
+    fn digest_file(&self, path: &Path) -> Result<Vec<Vec<u8>>> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+        let mut input = Input::File {
+            path: path.to_path_buf(),
+            reader: BufReader::new(file),
+        };
+        digest_input(&mut input, self.algorithms, self.key, self.pipeline)
+            .with_context(|| format!("Failed to read file: {}", path.display()))
+    }
+
+    fn digest_directory(&mut self, path: &Path) -> Result<Vec<Vec<u8>>> {
+        let canonical = fs::canonicalize(path)
+            .with_context(|| format!("Failed to resolve directory: {}", path.display()))?;
+        if !self.ancestors.insert(canonical.clone()) {
+            anyhow::bail!("Filesystem loop detected at {}", path.display());
+        }
+
+        let mut children = fs::read_dir(path)
+            .with_context(|| format!("Failed to read directory: {}", path.display()))?
+            .collect::<std::io::Result<Vec<_>>>()
+            .with_context(|| format!("Failed to read directory: {}", path.display()))?;
+        children.sort_by_key(|entry| entry.file_name());
+
+        let mut encoded = vec![TREE_PREFIX.to_vec(); self.algorithms.len()];
+        for child in children {
+            let child_path = child.path();
+            let Some((kind, digests)) = self.digest_entry(&child_path)? else {
+                continue;
+            };
+            let name = child.file_name().into_encoded_bytes();
+            for (encoded, digest) in encoded.iter_mut().zip(digests) {
+                encoded.push(kind);
+                encoded.extend_from_slice(&(name.len() as u64).to_be_bytes());
+                encoded.extend_from_slice(&name);
+                encoded.extend_from_slice(&digest);
+            }
+        }
+
+        self.ancestors.remove(&canonical);
+        let mut digests = Vec::with_capacity(encoded.len());
+        for (encoded, algorithm) in encoded.iter().zip(self.algorithms) {
+            let mut hasher = MultiHasher::new(&[*algorithm], self.key)?;
+            hasher.update(encoded);
+            digests.append(&mut hasher.finalize());
+        }
+        Ok(digests)
+    }
+
+    /// Returns the kind byte and digests of a directory entry, or `None` if it
+    /// is not part of the tree.
+    fn digest_entry(&mut self, path: &Path) -> Result<Option<(u8, Vec<Vec<u8>>)>> {
+        let mut metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to read metadata: {}", path.display()))?;
+        if metadata.file_type().is_symlink() {
+            match self.options.symlinks {
+                SymlinkMode::Skip => return Ok(None),
+                SymlinkMode::Target => {
+                    let target = fs::read_link(path)
+                        .with_context(|| format!("Failed to read link: {}", path.display()))?;
+                    let mut hasher = MultiHasher::new(self.algorithms, self.key)?;
+                    hasher.update(target.as_os_str().as_encoded_bytes());
+                    return Ok(Some((b'l', hasher.finalize())));
+                }
+                SymlinkMode::Follow => {
+                    metadata = fs::metadata(path)
+                        .with_context(|| format!("Failed to follow link: {}", path.display()))?;
+                }
+            }
+        }
+
+        if metadata.is_dir() {
+            if self.options.one_file_system && device_id(&metadata) != self.root_device {
+                return Ok(None);
+            }
+            Ok(Some((b'd', self.digest_directory(path)?)))
+        } else if metadata.is_file() {
+            Ok(Some((file_kind(&metadata), self.digest_file(path)?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn device_id(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+/// Returns the kind byte of a regular file.
+fn file_kind(metadata: &Metadata) -> u8 {
+    if is_executable(metadata) { b'x' } else { b'f' }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree_hex(path: &Path) -> String {
+        let digests = digest_tree(
+            path,
+            &[Algorithm::Sha256],
+            None,
+            Pipeline::default(),
+            &InputOptions::default(),
+        )
+        .unwrap();
+        hex::encode(&digests[0])
+    }
+
+    fn build_tree(root: &Path, files: &[(&str, &str)]) {
+        for (name, content) in files {
+            let path = root.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_tree_is_independent_of_creation_order() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        build_tree(first.path(), &[("a.txt", "a"), ("sub/b.txt", "b")]);
+        build_tree(second.path(), &[("sub/b.txt", "b"), ("a.txt", "a")]);
+
+        assert_eq!(tree_hex(first.path()), tree_hex(second.path()));
+    }
+
+    #[test]
+    fn test_tree_detects_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        build_tree(dir.path(), &[("a.txt", "a"), ("sub/b.txt", "b")]);
+        let original = tree_hex(dir.path());
+
+        // Content change
+        fs::write(dir.path().join("sub/b.txt"), "B").unwrap();
+        let changed = tree_hex(dir.path());
+        assert_ne!(original, changed);
+        fs::write(dir.path().join("sub/b.txt"), "b").unwrap();
+        assert_eq!(original, tree_hex(dir.path()));
+
+        // Rename
+        fs::rename(dir.path().join("a.txt"), dir.path().join("c.txt")).unwrap();
+        assert_ne!(original, tree_hex(dir.path()));
+        fs::rename(dir.path().join("c.txt"), dir.path().join("a.txt")).unwrap();
+
+        // Empty directories are part of the structure
+        fs::create_dir(dir.path().join("empty")).unwrap();
+        assert_ne!(original, tree_hex(dir.path()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tree_root_is_domain_separated() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        build_tree(dir.path(), &[("a.txt", "hello")]);
+        let file = dir.path().join("a.txt");
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        // H("root\0" || "f" || H("hello"))
+        assert_eq!(
+            tree_hex(&file),
+            "db1f80c10f0cd528c0f0f98bbb1cb7409198f4adeb53fcedca1575c665e27f6c"
+        );
+        // H("root\0" || "d" || H("tree\0" || "f" || u64_be(5) || "a.txt" || H("hello")))
+        assert_eq!(
+            tree_hex(dir.path()),
+            "4d72fae35e8e4a056cbd0fdb503aa1ee7a4467f7449ee5f658d1abb90816caf2"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tree_includes_executable_bit() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        build_tree(dir.path(), &[("run.sh", "#!/bin/sh")]);
+        let script = dir.path().join("run.sh");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o644)).unwrap();
+        let plain = tree_hex(dir.path());
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_ne!(plain, tree_hex(dir.path()));
+        // Only the executable bit matters, not the rest of the mode
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o744)).unwrap();
+        let executable = tree_hex(dir.path());
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o711)).unwrap();
+        assert_eq!(executable, tree_hex(dir.path()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tree_symlink_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("loop")).unwrap();
+
+        assert!(
+            digest_tree(
+                dir.path(),
+                &[Algorithm::Sha256],
+                None,
+                Pipeline::default(),
+                &InputOptions::default()
+            )
+            .is_err()
+        );
+
+        let options = InputOptions {
+            symlinks: SymlinkMode::Skip,
+            ..InputOptions::default()
+        };
+        assert!(
+            digest_tree(
+                dir.path(),
+                &[Algorithm::Sha256],
+                None,
+                Pipeline::default(),
+                &options
+            )
+            .is_ok()
+        );
+    }
+}
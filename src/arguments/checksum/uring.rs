@@ -0,0 +1,436 @@
+//! Batched file hashing through io_uring on Linux.
+//!
+//! Hashing millions of small files one by one is dominated by the `open`,
+//! `read` and `close` syscalls. Here up to `QUEUE_DEPTH` files are in flight
+//! at once: their opens, reads and closes are queued on a single ring and the
+//! completed buffers are fed to the hashers as they arrive. Digests are
+//! written in argument order, so the output is the same as `--checksum`.
+
+use anyhow::{Context, Result};
+use io_uring::{IoUring, Probe, opcode, squeue, types};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::io::{self, Cursor, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use super::{ChecksumOptions, FileStat, MultiHasher, digest_reader};
+use crate::utils::{InputOptions, Source, sources};
+
+/// Number of files open and being read at the same time.
+const QUEUE_DEPTH: u32 = 64;
+
+/// Size of each read submitted to the ring.
+const READ_SIZE: usize = 64 * 1024;
+
+/// Upper bound on finished inputs waiting for an earlier, slower one.
+const MAX_PENDING: usize = 4096;
+
+/// Returns a ring that supports every operation used here, or `None` if
+/// io_uring is unavailable (old kernel, seccomp, `io_uring_disabled`, ...).
+pub fn ring() -> Option<IoUring> {
+    let ring = IoUring::new(QUEUE_DEPTH).ok()?;
+    let mut probe = Probe::new();
+    ring.submitter().register_probe(&mut probe).ok()?;
+    [
+        opcode::OpenAt::CODE,
+        opcode::Read::CODE,
+        opcode::Close::CODE,
+    ]
+    .iter()
+    .all(|&code| probe.is_supported(code))
+    .then_some(ring)
+}
+
+/// Hashes `files` through `ring` and prints one line per input.
+///
+/// Behaves like `process_inputs_parallel` with `process_checksum_input`: the
+/// output of every input before a failing one is written, then its error is
+/// returned.
+///
+/// The kernel reads into the buffers of `slots` while their operations are in
+/// flight, so every error, including a failed write of the output, first
+/// drains the ring through the cancellation path and only then returns.
+pub fn process_checksum_batched<W: Write + ?Sized>(
+    mut ring: IoUring,
+    files: &[PathBuf],
+    input_options: &InputOptions,
+    writer: &mut W,
+    options: &ChecksumOptions,
+) -> Result<()> {
+    let mut sources = sources(files, input_options).enumerate().peekable();
+    let mut slots: Vec<Slot> = (0..QUEUE_DEPTH).map(|_| Slot::default()).collect();
+    let mut free: Vec<usize> = (0..slots.len()).rev().collect();
+    let mut pending: BTreeMap<usize, Result<Finished>> = BTreeMap::new();
+    let mut next_index = 0;
+    let mut failure = None;
+
+    loop {
+        // Start new inputs while there are free slots.
+        while failure.is_none() && !free.is_empty() {
+            let Some((index, _)) = sources.peek() else {
+                break;
+            };
+            if index - next_index >= MAX_PENDING {
+                break;
+            }
+            let (index, source) = sources.next().unwrap();
+            match source {
+                Ok(Source::File(path)) => {
+                    let slot = free.pop().unwrap();
+                    match slots[slot].start(index, path, options) {
+                        Ok(entry) => queue(&mut ring, entry, slot),
+                        Err(e) => {
+                            pending.insert(index, Err(e));
+                            free.push(slot);
+                        }
+                    }
+                }
+                Ok(Source::SymlinkTarget { path, target }) => {
+                    let digests = digest_reader(
+                        Cursor::new(&target),
+                        &options.algorithms,
+                        options.key.as_ref(),
+                    )
+                    .map_err(anyhow::Error::from);
+                    let display = path.display().to_string();
+                    let stat = FileStat {
+                        size: Some(target.len() as u64),
+                        mtime: None,
+                    };
+                    pending.insert(index, digests.map(|d| Finished::Digests(d, display, stat)));
+                }
+                Ok(Source::Skipped(message)) => {
+                    pending.insert(index, Ok(Finished::Skipped(message)));
+                }
+                Err(e) => {
+                    pending.insert(index, Err(e));
+                }
+            }
+        }
+
+        // Write finished inputs in argument order.
+        while failure.is_none()
+            && let Some(finished) = pending.remove(&next_index)
+        {
+            next_index += 1;
+            match finished {
+                Ok(Finished::Digests(digests, display, stat)) => {
+                    if let Err(e) = options.write_record(&mut *writer, &digests, &display, stat) {
+                        failure = Some(e.into());
+                    }
+                }
+                Ok(Finished::Skipped(message)) => eprintln!("{}", message),
+                Err(e) => failure = Some(e),
+            }
+        }
+
+        if free.len() == slots.len() {
+            if failure.is_some() || sources.peek().is_none() {
+                break;
+            }
+            continue;
+        }
+
+        match ring.submit_and_wait(1) {
+            Ok(_) => {}
+            // Retried once the completions available are handled below
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(libc::EINTR | libc::EAGAIN | libc::EBUSY)
+                ) => {}
+            Err(e) => {
+                // The ring is unusable, but operations still in flight may
+                // write into the slots, so their memory is never freed.
+                std::mem::forget(slots);
+                return Err(anyhow::Error::from(e).context("io_uring failed"));
+            }
+        }
+        let completions: Vec<(usize, i32)> = ring
+            .completion()
+            .map(|cqe| (cqe.user_data() as usize, cqe.result()))
+            .collect();
+        for (slot, result) in completions {
+            match slots[slot].complete(result, failure.is_some()) {
+                Step::Submit(entry) => queue(&mut ring, entry, slot),
+                Step::Done(index, finished) => {
+                    if let Some(finished) = finished {
+                        pending.insert(index, finished);
+                    }
+                    free.push(slot);
+                }
+            }
+        }
+    }
+
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Queues `entry` for `slot`; it is submitted by the next `submit_and_wait`.
+fn queue(ring: &mut IoUring, entry: squeue::Entry, slot: usize) {
+    let entry = entry.user_data(slot as u64);
+    // SAFETY: the path and buffer the entry points to are owned by the slot,
+    // which stays alive and untouched until the operation completes. Each
+    // slot has at most one operation in flight, so the queue never overflows.
+    unsafe {
+        ring.submission()
+            .push(&entry)
+            .expect("submission queue is larger than the number of slots");
+    }
+}
+
+/// A finished input, waiting to be written in order.
+enum Finished {
+    /// The digests, display string and stat of a hashed input.
+    Digests(Vec<Vec<u8>>, String, FileStat),
+    /// The message explaining why the input is skipped.
+    Skipped(String),
+}
+
+/// What a slot needs next after one of its operations completed.
+enum Step {
+    /// Submit another operation for the same input.
+    Submit(squeue::Entry),
+    /// The input is finished and the slot is free again.
+    Done(usize, Option<Result<Finished>>),
+}
+
+/// Buffer and state of one file in flight, from `openat` to `close`.
+struct Slot {
+    buffer: Vec<u8>,
+    job: Option<Job>,
+}
+
+struct Job {
+    index: usize,
+    path: PathBuf,
+    offset: u64,
+    hasher: Option<MultiHasher>,
+    /// Whether to look up the size and modification time once hashed.
+    stat: bool,
+    state: State,
+}
+
+/// The operation a `Job` is waiting for.
+enum State {
+    /// `openat` of the path, which must stay alive until it completes.
+    Opening(#[allow(dead_code)] CString),
+    /// A read from the open file.
+    Reading(types::Fd),
+    /// `close` of the file, then the outcome to report (none if cancelled).
+    Closing(Option<Result<Finished>>),
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        Self {
+            buffer: vec![0; READ_SIZE],
+            job: None,
+        }
+    }
+}
+
+impl Slot {
+    /// Starts hashing `path` and returns the `openat` to submit.
+    fn start(
+        &mut self,
+        index: usize,
+        path: PathBuf,
+        options: &ChecksumOptions,
+    ) -> Result<squeue::Entry> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .with_context(|| format!("Failed to open file: {}", path.display()))?;
+        let entry = opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), c_path.as_ptr())
+            .flags(libc::O_RDONLY | libc::O_CLOEXEC)
+            .build();
+        self.job = Some(Job {
+            index,
+            path,
+            offset: 0,
+            hasher: Some(MultiHasher::new(&options.algorithms, options.key.as_ref())?),
+            stat: options.needs_stat(),
+            state: State::Opening(c_path),
+        });
+        Ok(entry)
+    }
+
+    /// Handles the completion of the slot's operation with kernel `result`.
+    ///
+    /// With `cancelled`, an open file is closed instead of read further.
+    fn complete(&mut self, result: i32, cancelled: bool) -> Step {
+        let job = self.job.as_mut().expect("completion for an idle slot");
+        let state = std::mem::replace(&mut job.state, State::Closing(None));
+        match state {
+            State::Opening(_) if result < 0 => {
+                let job = self.job.take().unwrap();
+                let error = anyhow::Error::from(io::Error::from_raw_os_error(-result))
+                    .context(format!("Failed to open file: {}", job.path.display()));
+                Step::Done(job.index, Some(Err(error)))
+            }
+            State::Opening(_) => self.read(types::Fd(result), cancelled),
+            State::Reading(fd) if cancelled => self.close(fd, None),
+            State::Reading(fd) if result > 0 => {
+                let read = result as usize;
+                job.hasher.as_mut().unwrap().update(&self.buffer[..read]);
+                job.offset += read as u64;
+                self.read(fd, cancelled)
+            }
+            State::Reading(fd) if result == 0 => {
+                let digests = job.hasher.take().unwrap().finalize();
+                let display = job.path.display().to_string();
+                let stat = if job.stat {
+                    std::fs::metadata(&job.path).map(|metadata| FileStat::from(&metadata))
+                } else {
+                    Ok(FileStat::default())
+                };
+                let finished = stat
+                    .map(|stat| Finished::Digests(digests, display, stat))
+                    .map_err(anyhow::Error::from);
+                self.close(fd, Some(finished))
+            }
+            State::Reading(fd) => {
+                let error = io::Error::from_raw_os_error(-result);
+                self.close(fd, Some(Err(error.into())))
+            }
+            State::Closing(finished) => {
+                let job = self.job.take().unwrap();
+                Step::Done(job.index, finished)
+            }
+        }
+    }
+
+    /// Reads the next buffer of `fd`, or closes it if `cancelled`.
+    fn read(&mut self, fd: types::Fd, cancelled: bool) -> Step {
+        if cancelled {
+            return self.close(fd, None);
+        }
+        let job = self.job.as_mut().unwrap();
+        job.state = State::Reading(fd);
+        let entry = opcode::Read::new(fd, self.buffer.as_mut_ptr(), READ_SIZE as u32)
+            .offset(job.offset)
+            .build();
+        Step::Submit(entry)
+    }
+
+    /// Closes `fd`, reporting `finished` once the close completes.
+    fn close(&mut self, fd: types::Fd, finished: Option<Result<Finished>>) -> Step {
+        self.job.as_mut().unwrap().state = State::Closing(finished);
+        Step::Submit(opcode::Close::new(fd).build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arguments::checksum::{Algorithm, process_checksum_input};
+    use crate::utils::process_inputs_with;
+
+    fn write_files(dir: &std::path::Path, count: usize) -> Vec<PathBuf> {
+        (0..count)
+            .map(|i| {
+                let path = dir.join(format!("file_{:03}", i));
+                // Include files larger than one read
+                std::fs::write(&path, i.to_string().repeat(i * 1000)).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn sequential(files: &[PathBuf], options: &ChecksumOptions) -> (Vec<u8>, bool) {
+        let mut writer = Vec::new();
+        let result = process_inputs_with(
+            files,
+            &InputOptions::default(),
+            &mut writer,
+            |input, path, w| process_checksum_input(input, path, w, options),
+        );
+        (writer, result.is_ok())
+    }
+
+    fn batched(files: &[PathBuf], options: &ChecksumOptions) -> Option<(Vec<u8>, bool)> {
+        let ring = ring()?;
+        let mut writer = Vec::new();
+        let result =
+            process_checksum_batched(ring, files, &InputOptions::default(), &mut writer, options);
+        Some((writer, result.is_ok()))
+    }
+
+    #[test]
+    fn test_batched_matches_sequential() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = write_files(dir.path(), 200);
+        let options = Algorithm::Blake3.into();
+
+        let Some(output) = batched(&files, &options) else {
+            eprintln!("io_uring is unavailable, skipping");
+            return;
+        };
+        assert_eq!(output, sequential(&files, &options));
+        assert!(output.1);
+    }
+
+    #[test]
+    fn test_batched_stops_at_first_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = write_files(dir.path(), 100);
+        // A regular file that cannot be read from offset 0
+        files.insert(70, PathBuf::from("/proc/self/mem"));
+        let options = Algorithm::Sha256.into();
+
+        let Some((output, ok)) = batched(&files, &options) else {
+            eprintln!("io_uring is unavailable, skipping");
+            return;
+        };
+        assert!(!ok);
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 70);
+        assert_eq!((Vec::new(), false), sequential(&files[70..], &options));
+    }
+
+    /// Writer that accepts `limit` writes, then fails like a closed pipe.
+    struct Closing {
+        limit: usize,
+    }
+
+    impl Write for Closing {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.limit == 0 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.limit -= 1;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_batched_write_error_drains_ring() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = write_files(dir.path(), 200);
+        let options = Algorithm::Sha256.into();
+
+        let Some(ring) = ring() else {
+            eprintln!("io_uring is unavailable, skipping");
+            return;
+        };
+        let mut writer = Closing { limit: 3 };
+        let error = process_checksum_batched(
+            ring,
+            &files,
+            &InputOptions::default(),
+            &mut writer,
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<io::Error>().map(io::Error::kind),
+            Some(io::ErrorKind::BrokenPipe)
+        );
+    }
+}
@@ -1,5 +1,7 @@
 pub mod checksum;
 pub mod parse;
+pub mod signature;
+pub mod store;
 
 use crate::traits::CommandArg;
 
@@ -8,5 +10,9 @@ pub fn arguments() -> Vec<Box<dyn CommandArg>> {
     vec![
         Box::new(checksum::ChecksumArgument::new()),
         Box::new(parse::ParseArgument::new()),
+        Box::new(store::StoreArgument::new()),
+        Box::new(signature::KeygenArgument::new()),
+        Box::new(signature::SignArgument::new()),
+        Box::new(signature::VerifySignatureArgument::new()),
     ]
 }
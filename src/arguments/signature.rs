@@ -0,0 +1,316 @@
+//! Detached Ed25519 signatures over checksum manifests, for `--keygen`,
+//! `--sign` and `--verify-signature`; `--checksum --check --signed-by` checks
+//! a manifest's signature before its files.
+//!
+//! Keys are PEM files: the secret key is PKCS#8 and the public key is
+//! SubjectPublicKeyInfo, the formats `openssl genpkey -algorithm ed25519`
+//! writes. A signature is the raw 64 bytes in `<manifest>.sig`, covering the
+//! manifest byte for byte, so OpenSSL can check it as well:
+//!
+//! ```text
+//! openssl pkeyutl -verify -pubin -inkey release.pub -rawin -in SUMS -sigfile SUMS.sig
+//! ```
+
+use anyhow::{Context, Result};
+use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+use crate::traits::CommandArg;
+use crate::utils::SilentFailure;
+
+/// Argument handler for the keygen command.
+#[derive(Debug, Default)]
+pub struct KeygenArgument {}
+
+impl KeygenArgument {
+    /// Creates a new `KeygenArgument`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CommandArg for KeygenArgument {
+    fn name(&self) -> &'static str {
+        "keygen"
+    }
+
+    fn build(&self) -> clap::Arg {
+        clap::Arg::new(self.name())
+            .long(self.name())
+            .help("Generate an Ed25519 key pair for --sign, written to KEY and KEY.pub")
+            .value_name("KEY")
+            .value_parser(clap::value_parser!(PathBuf))
+    }
+
+    fn run(&self, matches: &clap::ArgMatches, _writer: &mut dyn std::io::Write) -> Result<()> {
+        let Some(path) = matches.get_one::<PathBuf>(self.name()) else {
+            return Ok(());
+        };
+        let public = keygen(path)?;
+        eprintln!(
+            "Wrote secret key {} and public key {}",
+            path.display(),
+            public.display()
+        );
+        Ok(())
+    }
+}
+
+/// Argument handler for the sign command.
+#[derive(Debug, Default)]
+pub struct SignArgument {}
+
+impl SignArgument {
+    /// Creates a new `SignArgument`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CommandArg for SignArgument {
+    fn name(&self) -> &'static str {
+        "sign"
+    }
+
+    fn build(&self) -> clap::Arg {
+        clap::Arg::new(self.name())
+            .long(self.name())
+            .help("Write a detached Ed25519 signature of each manifest to <manifest>.sig")
+            .value_names(["KEY", "MANIFEST"])
+            .num_args(2..)
+            .value_parser(clap::value_parser!(PathBuf))
+    }
+
+    fn run(&self, matches: &clap::ArgMatches, _writer: &mut dyn std::io::Write) -> Result<()> {
+        let Some(mut values) = matches.get_many::<PathBuf>(self.name()) else {
+            return Ok(());
+        };
+        let key = read_signing_key(values.next().unwrap())?;
+        for manifest in values {
+            sign(manifest, &key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Argument handler for the verify-signature command.
+#[derive(Debug, Default)]
+pub struct VerifySignatureArgument {}
+
+impl VerifySignatureArgument {
+    /// Creates a new `VerifySignatureArgument`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CommandArg for VerifySignatureArgument {
+    fn name(&self) -> &'static str {
+        "verify-signature"
+    }
+
+    fn build(&self) -> clap::Arg {
+        clap::Arg::new(self.name())
+            .long(self.name())
+            .help("Check each manifest against <manifest>.sig with this public key")
+            .value_names(["PUBLIC_KEY", "MANIFEST"])
+            .num_args(2..)
+            .value_parser(clap::value_parser!(PathBuf))
+    }
+
+    /// Prints `<manifest>: signature OK` or `FAILED` for each manifest, and
+    /// fails if any signature doesn't match.
+    fn run(&self, matches: &clap::ArgMatches, writer: &mut dyn std::io::Write) -> Result<()> {
+        let Some(mut values) = matches.get_many::<PathBuf>(self.name()) else {
+            return Ok(());
+        };
+        let key = read_verifying_key(values.next().unwrap())?;
+        let mut failed = false;
+        for manifest in values {
+            let status = match verify(manifest, &key)? {
+                Some(_) => "OK",
+                None => {
+                    failed = true;
+                    "FAILED"
+                }
+            };
+            writeln!(writer, "{}: signature {}", manifest.display(), status)?;
+        }
+        if failed {
+            return Err(SilentFailure.into());
+        }
+        Ok(())
+    }
+}
+
+/// Returns the path of the detached signature of `manifest`.
+pub fn signature_path(manifest: &Path) -> PathBuf {
+    let mut path = manifest.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Returns the path `keygen` writes the public key of `secret` to.
+pub fn public_key_path(secret: &Path) -> PathBuf {
+    let mut path = secret.as_os_str().to_owned();
+    path.push(".pub");
+    PathBuf::from(path)
+}
+
+/// Generates a key pair and writes the secret key to `secret` and the public
+/// key to `<secret>.pub`. Existing files are never overwritten, and the
+/// secret key is only readable by its owner. If the public key can't be
+/// written, the secret key is removed again, so a failed run can be retried.
+pub fn keygen(secret: &Path) -> Result<PathBuf> {
+    let mut seed = Zeroizing::new([0; ed25519_dalek::SECRET_KEY_LENGTH]);
+    getrandom::fill(&mut *seed).context("Failed to gather randomness for the key")?;
+    let signing_key = SigningKey::from_bytes(&seed);
+    let secret_pem = signing_key
+        .to_pkcs8_pem(LineEnding::LF)
+        .context("Failed to encode the secret key")?;
+    let public_pem = signing_key
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .context("Failed to encode the public key")?;
+
+    let public = public_key_path(secret);
+    write_new(secret, secret_pem.as_bytes(), 0o600)
+        .with_context(|| format!("Failed to write key file: {}", secret.display()))?;
+    if let Err(error) = write_new(&public, public_pem.as_bytes(), 0o644) {
+        let _ = std::fs::remove_file(secret);
+        return Err(anyhow::Error::from(error)
+            .context(format!("Failed to write key file: {}", public.display())));
+    }
+    Ok(public)
+}
+
+/// Creates `path`, which must not exist, with `contents`. A partially
+/// written file is removed.
+fn write_new(path: &Path, contents: &[u8], #[allow(unused)] mode: u32) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    let mut file = options.open(path)?;
+    file.write_all(contents)
+        .and_then(|()| file.sync_all())
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(path);
+        })
+}
+
+/// Reads a PKCS#8 PEM secret key.
+pub fn read_signing_key(path: &Path) -> Result<SigningKey> {
+    let pem = Zeroizing::new(
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read key file: {}", path.display()))?,
+    );
+    SigningKey::from_pkcs8_pem(&pem)
+        .map_err(|_| anyhow::anyhow!("Not an Ed25519 secret key: {}", path.display()))
+}
+
+/// Reads a SubjectPublicKeyInfo PEM public key.
+pub fn read_verifying_key(path: &Path) -> Result<VerifyingKey> {
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read key file: {}", path.display()))?;
+    VerifyingKey::from_public_key_pem(&pem)
+        .map_err(|_| anyhow::anyhow!("Not an Ed25519 public key: {}", path.display()))
+}
+
+/// Signs `manifest` and writes the signature to `<manifest>.sig`.
+pub fn sign(manifest: &Path, key: &SigningKey) -> Result<PathBuf> {
+    use ed25519_dalek::Signer;
+
+    let contents = std::fs::read(manifest)
+        .with_context(|| format!("Failed to read manifest: {}", manifest.display()))?;
+    let signature = key.sign(&contents);
+    let path = signature_path(manifest);
+    std::fs::write(&path, signature.to_bytes())
+        .with_context(|| format!("Failed to write signature: {}", path.display()))?;
+    Ok(path)
+}
+
+/// Reads `manifest` and checks it against `<manifest>.sig`.
+///
+/// Returns the manifest contents that were verified, so callers can parse
+/// exactly those bytes rather than reading the file a second time. Returns
+/// `Ok(None)` if the signature does not match; I/O errors and malformed
+/// signature files are errors.
+pub fn verify(manifest: &Path, key: &VerifyingKey) -> Result<Option<Vec<u8>>> {
+    let contents = std::fs::read(manifest)
+        .with_context(|| format!("Failed to read manifest: {}", manifest.display()))?;
+    let path = signature_path(manifest);
+    let bytes = std::fs::read(&path)
+        .with_context(|| format!("Failed to read signature: {}", path.display()))?;
+    let signature = Signature::from_slice(&bytes)
+        .map_err(|_| anyhow::anyhow!("Not an Ed25519 signature: {}", path.display()))?;
+    Ok(key
+        .verify_strict(&contents, &signature)
+        .is_ok()
+        .then_some(contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = dir.path().join("release.key");
+        let public = keygen(&secret).unwrap();
+        assert_eq!(public, dir.path().join("release.key.pub"));
+        assert!(keygen(&secret).is_err(), "keygen must not overwrite keys");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&secret).unwrap().permissions().mode();
+            assert_eq!(mode & 0o077, 0);
+        }
+
+        let manifest = dir.path().join("SUMS");
+        std::fs::write(
+            &manifest,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  hello\n",
+        )
+        .unwrap();
+        let signing_key = read_signing_key(&secret).unwrap();
+        let verifying_key = read_verifying_key(&public).unwrap();
+        assert_eq!(
+            sign(&manifest, &signing_key).unwrap(),
+            dir.path().join("SUMS.sig")
+        );
+        assert_eq!(
+            verify(&manifest, &verifying_key).unwrap(),
+            Some(std::fs::read(&manifest).unwrap())
+        );
+
+        std::fs::write(&manifest, "tampered\n").unwrap();
+        assert_eq!(verify(&manifest, &verifying_key).unwrap(), None);
+
+        // A key file of the wrong kind is rejected
+        assert!(read_verifying_key(&secret).is_err());
+        assert!(read_signing_key(&public).is_err());
+    }
+
+    #[test]
+    fn test_keygen_keeps_no_secret_without_public_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = dir.path().join("release.key");
+        let public = public_key_path(&secret);
+        std::fs::write(&public, "existing").unwrap();
+
+        assert!(keygen(&secret).is_err());
+        assert!(!secret.exists());
+        assert_eq!(std::fs::read_to_string(&public).unwrap(), "existing");
+
+        // Once the old public key is gone, keygen can run again
+        std::fs::remove_file(&public).unwrap();
+        assert_eq!(keygen(&secret).unwrap(), public);
+        assert!(secret.exists());
+    }
+}
@@ -0,0 +1,468 @@
+//! Content-addressable blob store.
+//!
+//! Blobs are named by their SHA-256 digest and kept as
+//! `<root>/sha256/<first two hex digits>/<remaining hex digits>`. `--add`
+//! streams its input into a temporary file under `<root>/tmp` while hashing
+//! it and then renames the file into place, so a blob is either complete or
+//! absent, even when several processes add the same contents at once.
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::arguments::checksum::check::{parse_record, plural};
+use crate::arguments::checksum::escape::escape_filename;
+use crate::arguments::checksum::{Algorithm, Pipeline, digest_input, digest_reader};
+use crate::traits::CommandArg;
+use crate::utils::{Input, SilentFailure, process_inputs};
+
+/// Directory below the root that holds the blobs.
+const BLOBS_DIR: &str = "sha256";
+
+/// Directory below the root that holds blobs being added.
+const TEMP_DIR: &str = "tmp";
+
+/// Age after which `--gc` removes a temporary file left by an interrupted
+/// `--add`.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Length of a SHA-256 digest in bytes.
+const DIGEST_LEN: usize = 32;
+
+/// Argument handler for the store command.
+#[derive(Debug, Default)]
+pub struct StoreArgument {}
+
+impl StoreArgument {
+    /// Creates a new `StoreArgument`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CommandArg for StoreArgument {
+    fn name(&self) -> &'static str {
+        "store"
+    }
+
+    fn build(&self) -> clap::Arg {
+        clap::Arg::new(self.name())
+            .long(self.name())
+            .help("Add, read or check blobs in the content-addressable store at this directory")
+            .value_name("ROOT")
+            .value_parser(clap::value_parser!(PathBuf))
+    }
+
+    fn options(&self) -> Vec<clap::Arg> {
+        vec![
+            clap::Arg::new("add")
+                .long("add")
+                .help("Copy files (or stdin) into the store and print their digests")
+                .num_args(0..)
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all(["get", "has", "gc", "verify"]),
+            clap::Arg::new("get")
+                .long("get")
+                .help("Write the blob with this SHA-256 digest to stdout")
+                .value_name("HASH")
+                .value_parser(parse_hash)
+                .conflicts_with_all(["has", "gc", "verify"]),
+            clap::Arg::new("has")
+                .long("has")
+                .help("Print the digests that are not in the store; fail if there are any")
+                .value_name("HASH")
+                .num_args(1..)
+                .value_parser(parse_hash)
+                .conflicts_with_all(["gc", "verify"]),
+            clap::Arg::new("gc")
+                .long("gc")
+                .help("Remove blobs not listed in the given manifests, and stale temporary files")
+                .value_name("MANIFEST")
+                .num_args(0..)
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with("verify"),
+            clap::Arg::new("verify")
+                .long("verify")
+                .help("Re-hash every blob and report those whose contents changed")
+                .action(clap::ArgAction::SetTrue),
+        ]
+    }
+
+    fn run(&self, matches: &clap::ArgMatches, writer: &mut dyn std::io::Write) -> Result<()> {
+        let Some(root) = matches.get_one::<PathBuf>(self.name()) else {
+            return Ok(());
+        };
+        let store = Store::new(root.clone());
+
+        if let Some(files) = matches.get_many::<PathBuf>("add") {
+            let files: Vec<_> = files.cloned().collect();
+            return process_inputs(&files, writer, |input, path_display, writer| {
+                let digest = store
+                    .add(input)
+                    .with_context(|| format!("Failed to add to store: {}", path_display))?;
+                match escape_filename(path_display) {
+                    Some(escaped) => writeln!(writer, "\\{}  {}", hex::encode(digest), escaped)?,
+                    None => writeln!(writer, "{}  {}", hex::encode(digest), path_display)?,
+                }
+                Ok(())
+            });
+        }
+        if let Some(digest) = matches.get_one::<Vec<u8>>("get") {
+            let mut blob = store.open(digest)?;
+            std::io::copy(&mut blob, writer)
+                .with_context(|| format!("Failed to read blob: {}", hex::encode(digest)))?;
+            return Ok(());
+        }
+        if let Some(digests) = matches.get_many::<Vec<u8>>("has") {
+            let mut missing = false;
+            for digest in digests {
+                if !store.contains(digest) {
+                    writeln!(writer, "{}", hex::encode(digest))?;
+                    missing = true;
+                }
+            }
+            if missing {
+                return Err(SilentFailure.into());
+            }
+            return Ok(());
+        }
+        if let Some(manifests) = matches.get_many::<PathBuf>("gc") {
+            let manifests: Vec<_> = manifests.collect();
+            let mut keep = HashSet::new();
+            for manifest in &manifests {
+                read_keep_list(manifest, &mut keep)?;
+            }
+            // Without manifests there is no way to tell which blobs are in use
+            let (removed, freed) = store.gc((!manifests.is_empty()).then_some(&keep))?;
+            eprintln!(
+                "Removed {}, freeing {} bytes",
+                plural(removed, "blob", "blobs"),
+                freed
+            );
+            return Ok(());
+        }
+        if matches.get_flag("verify") {
+            let failures = store.verify(writer)?;
+            if failures > 0 {
+                eprintln!(
+                    "WARNING: {}",
+                    plural(
+                        failures,
+                        "blob is corrupt or unreadable",
+                        "blobs are corrupt or unreadable"
+                    )
+                );
+                return Err(SilentFailure.into());
+            }
+            return Ok(());
+        }
+        anyhow::bail!("--store needs one of --add, --get, --has, --gc or --verify");
+    }
+}
+
+/// Parses a SHA-256 digest given in hex on the command line.
+fn parse_hash(text: &str) -> Result<Vec<u8>, String> {
+    match hex::decode(text) {
+        Ok(digest) if digest.len() == DIGEST_LEN => Ok(digest),
+        _ => Err(format!("expected {} hex digits", DIGEST_LEN * 2)),
+    }
+}
+
+/// Adds the SHA-256 digest of every line of a manifest to `keep`.
+///
+/// Lines are `sha256sum`-style checksum lines, like `--add` prints, or bare
+/// digests. Any other line is an error, since skipping it could delete a blob
+/// that is still needed.
+fn read_keep_list(manifest: &Path, keep: &mut HashSet<Vec<u8>>) -> Result<()> {
+    let file = File::open(manifest)
+        .with_context(|| format!("Failed to open file: {}", manifest.display()))?;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read: {}", manifest.display()))?;
+        if line.is_empty() {
+            continue;
+        }
+        let digest = match parse_record(&line, Algorithm::Sha256, false, false) {
+            Some(entry) if entry.algorithm == Algorithm::Sha256 && !entry.keyed => {
+                Some(entry.digest)
+            }
+            Some(_) => None,
+            None => parse_hash(&line).ok(),
+        };
+        let Some(digest) = digest else {
+            anyhow::bail!(
+                "{}: {}: not a SHA-256 checksum line; nothing was removed",
+                manifest.display(),
+                index + 1
+            );
+        };
+        keep.insert(digest);
+    }
+    Ok(())
+}
+
+/// A blob store rooted at a directory.
+pub struct Store {
+    root: PathBuf,
+}
+
+impl Store {
+    /// Opens the store at `root`. Directories are created on first `add`.
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// Returns the path of the blob with `digest`.
+    pub fn blob_path(&self, digest: &[u8]) -> PathBuf {
+        let hex = hex::encode(digest);
+        self.root.join(BLOBS_DIR).join(&hex[..2]).join(&hex[2..])
+    }
+
+    /// Copies everything `reader` yields into the store and returns its
+    /// digest. Contents already in the store are not written again.
+    pub fn add<R: Read>(&self, reader: R) -> Result<Vec<u8>> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let temp_dir = self.root.join(TEMP_DIR);
+        fs::create_dir_all(&temp_dir)
+            .with_context(|| format!("Failed to create directory: {}", temp_dir.display()))?;
+        let temp = temp_dir.join(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let write = || -> std::io::Result<Vec<u8>> {
+            let mut file = BufWriter::new(File::create_new(&temp)?);
+            let tee = Tee {
+                reader,
+                writer: &mut file,
+            };
+            let digest = digest_reader(tee, &[Algorithm::Sha256], None)?.remove(0);
+            file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            let path = self.blob_path(&digest);
+            if !path.exists() {
+                fs::create_dir_all(path.parent().expect("blob path has a parent"))?;
+                fs::rename(&temp, &path)?;
+            }
+            Ok(digest)
+        };
+        let result = write();
+        // Gone already if it was renamed into place
+        let _ = fs::remove_file(&temp);
+        Ok(result?)
+    }
+
+    /// Opens the blob with `digest` for reading.
+    pub fn open(&self, digest: &[u8]) -> Result<File> {
+        let path = self.blob_path(digest);
+        File::open(&path).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                anyhow::anyhow!("Blob not in store: {}", hex::encode(digest))
+            } else {
+                anyhow::Error::from(e).context(format!("Failed to open blob: {}", path.display()))
+            }
+        })
+    }
+
+    /// Returns `true` if the store has a blob with `digest`.
+    pub fn contains(&self, digest: &[u8]) -> bool {
+        self.blob_path(digest).is_file()
+    }
+
+    /// Returns the digest and path of every blob, sorted by digest. Files
+    /// whose path is not a digest are left out.
+    pub fn blobs(&self) -> Result<Vec<(Vec<u8>, PathBuf)>> {
+        let dir = self.root.join(BLOBS_DIR);
+        let mut blobs = Vec::new();
+        let subdirs = match fs::read_dir(&dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(blobs),
+            subdirs => subdirs.with_context(|| format!("Failed to read: {}", dir.display()))?,
+        };
+        for subdir in subdirs {
+            let subdir = subdir?;
+            let prefix = subdir.file_name().to_string_lossy().into_owned();
+            if !subdir.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(subdir.path())? {
+                let entry = entry?;
+                let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+                if let Ok(digest) = parse_hash(&name)
+                    && entry.file_type()?.is_file()
+                    && hex::encode(&digest) == name
+                {
+                    blobs.push((digest, entry.path()));
+                }
+            }
+        }
+        blobs.sort();
+        Ok(blobs)
+    }
+
+    /// Re-hashes every blob and prints `<hash>: FAILED` for each one whose
+    /// contents no longer match its name. Returns the number of failures.
+    pub fn verify<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let mut failures = 0;
+        for (digest, path) in self.blobs()? {
+            let hex = hex::encode(&digest);
+            let actual = File::open(&path).and_then(|file| {
+                let mut input = Input::File {
+                    path,
+                    reader: BufReader::new(file),
+                };
+                digest_input(&mut input, &[Algorithm::Sha256], None, Pipeline::default())
+            });
+            match actual {
+                Ok(actual) if actual[0] == digest => {}
+                Ok(_) => {
+                    failures += 1;
+                    writeln!(writer, "{}: FAILED", hex)?;
+                }
+                Err(e) => {
+                    failures += 1;
+                    eprintln!("{}: {}", hex, e);
+                    writeln!(writer, "{}: FAILED open or read", hex)?;
+                }
+            }
+        }
+        Ok(failures)
+    }
+
+    /// Removes temporary files older than `STALE_TEMP_AGE` and, if `keep` is
+    /// given, every blob not in it. Returns the number of blobs removed and
+    /// the bytes they used.
+    pub fn gc(&self, keep: Option<&HashSet<Vec<u8>>>) -> Result<(usize, u64)> {
+        let temp_dir = self.root.join(TEMP_DIR);
+        if let Ok(entries) = fs::read_dir(&temp_dir) {
+            let now = SystemTime::now();
+            for entry in entries {
+                let entry = entry?;
+                let stale = entry.metadata()?.modified().is_ok_and(|mtime| {
+                    now.duration_since(mtime).unwrap_or_default() > STALE_TEMP_AGE
+                });
+                if stale {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+
+        let Some(keep) = keep else {
+            return Ok((0, 0));
+        };
+        let (mut removed, mut freed) = (0, 0);
+        for (digest, path) in self.blobs()? {
+            if keep.contains(&digest) {
+                continue;
+            }
+            let size = fs::metadata(&path)?.len();
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove blob: {}", path.display()))?;
+            // Only succeeds once the last blob of the fan-out directory is gone
+            if let Some(parent) = path.parent() {
+                let _ = fs::remove_dir(parent);
+            }
+            removed += 1;
+            freed += size;
+        }
+        Ok((removed, freed))
+    }
+}
+
+/// Reader that copies everything it reads into `writer`.
+struct Tee<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.writer.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_store_add_get_has() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path().to_path_buf());
+        let digest = store.add(Cursor::new("hello")).unwrap();
+        assert_eq!(hex::encode(&digest), HELLO_SHA256);
+        assert!(
+            dir.path()
+                .join("sha256/2c")
+                .join(&HELLO_SHA256[2..])
+                .is_file()
+        );
+        // Adding the same contents again leaves no temporary file behind
+        assert_eq!(store.add(Cursor::new("hello")).unwrap(), digest);
+        assert_eq!(fs::read_dir(dir.path().join("tmp")).unwrap().count(), 0);
+
+        let mut contents = String::new();
+        store
+            .open(&digest)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "hello");
+        assert!(store.contains(&digest));
+        assert!(!store.contains(&[0; DIGEST_LEN]));
+        assert!(store.open(&[0; DIGEST_LEN]).is_err());
+    }
+
+    #[test]
+    fn test_store_verify_and_gc() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path().to_path_buf());
+        let hello = store.add(Cursor::new("hello")).unwrap();
+        let world = store.add(Cursor::new("world")).unwrap();
+
+        let mut writer = Vec::new();
+        assert_eq!(store.verify(&mut writer).unwrap(), 0);
+        fs::write(store.blob_path(&world), "w0rld").unwrap();
+        assert_eq!(store.verify(&mut writer).unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            format!("{}: FAILED\n", hex::encode(&world))
+        );
+
+        assert_eq!(store.gc(None).unwrap(), (0, 0));
+        let keep = HashSet::from([hello.clone()]);
+        assert_eq!(store.gc(Some(&keep)).unwrap(), (1, 5));
+        assert!(store.contains(&hello));
+        assert!(!store.contains(&world));
+        assert!(!store.blob_path(&world).parent().unwrap().exists());
+    }
+
+    #[test]
+    fn test_read_keep_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("SUMS");
+        fs::write(
+            &manifest,
+            format!(
+                "{}  hello.txt\n\n{}\n",
+                HELLO_SHA256,
+                "00".repeat(DIGEST_LEN)
+            ),
+        )
+        .unwrap();
+        let mut keep = HashSet::new();
+        read_keep_list(&manifest, &mut keep).unwrap();
+        assert_eq!(keep.len(), 2);
+        assert!(keep.contains(&hex::decode(HELLO_SHA256).unwrap()));
+
+        fs::write(&manifest, "not a checksum\n").unwrap();
+        assert!(read_keep_list(&manifest, &mut keep).is_err());
+    }
+}
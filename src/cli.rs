@@ -29,6 +29,9 @@ impl Cli {
 
         for arg in &arguments {
             app = app.arg(arg.build());
+            for option in arg.options() {
+                app = app.arg(option.requires(arg.name()));
+            }
         }
 
         // Add completions command
@@ -4,7 +4,7 @@
 //!
 //! It supports:
 //! - Streaming JSON parsing avoiding memory overhead.
-//! - SHA256 checksum calculation for files.
+//! - SHA-2 and SHA-3 checksum calculation for files.
 //! - Input from both files and standard input.
 
 pub mod arguments;
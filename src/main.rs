@@ -1,7 +1,20 @@
 use anyhow::Result;
 use my_app::runner;
+use my_app::utils::{SilentFailure, Trouble};
 
 fn main() -> Result<()> {
     let mut stdout = std::io::stdout();
-    runner::run(std::env::args(), &mut stdout)
+    let result = runner::run(std::env::args(), &mut stdout);
+    if let Err(e) = &result
+        && e.is::<SilentFailure>()
+    {
+        std::process::exit(1);
+    }
+    if let Err(e) = &result
+        && e.is::<Trouble>()
+    {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(2);
+    }
+    result
 }
@@ -6,6 +6,13 @@ pub trait CommandArg {
     /// Builds the `clap::Arg` definition.
     fn build(&self) -> clap::Arg;
 
+    /// Builds the `clap::Arg` definitions of options that modify this argument.
+    ///
+    /// Options are only meaningful together with the argument itself.
+    fn options(&self) -> Vec<clap::Arg> {
+        Vec::new()
+    }
+
     /// Executes the logic associated with the argument if present.
     fn run(
         &self,
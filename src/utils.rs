@@ -1,7 +1,70 @@
 use anyhow::{Context, Result};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{BufReader, Read, Write};
-use std::path::PathBuf;
+use std::io::{BufReader, Cursor, Read, StdinLock, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use walkdir::WalkDir;
+
+/// How `process_inputs_with` treats symbolic links found inside directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SymlinkMode {
+    /// Follow links to files and directories.
+    #[default]
+    Follow,
+    /// Ignore links.
+    Skip,
+    /// Hash the path the link points to instead of the file behind it.
+    Target,
+}
+
+/// Options controlling how `process_inputs_with` expands its arguments.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InputOptions {
+    /// Walk directory arguments instead of skipping them.
+    pub recursive: bool,
+    /// Symlink handling while walking directories.
+    pub symlinks: SymlinkMode,
+    /// Don't descend into directories on other filesystems.
+    pub one_file_system: bool,
+}
+
+/// An input stream handed to the `process_inputs` callback.
+pub enum Input {
+    /// Standard input.
+    Stdin(StdinLock<'static>),
+    /// A regular file opened from `path`.
+    File {
+        /// Path the file was opened from.
+        path: PathBuf,
+        /// Buffered reader over the open file.
+        reader: BufReader<File>,
+    },
+    /// The target path of a symbolic link, see `SymlinkMode::Target`.
+    SymlinkTarget(Cursor<Vec<u8>>),
+}
+
+impl Input {
+    /// Returns the path of the input if it is a regular file.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Input::Stdin(_) | Input::SymlinkTarget(_) => None,
+            Input::File { path, .. } => Some(path),
+        }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Input::Stdin(reader) => reader.read(buf),
+            Input::File { reader, .. } => reader.read(buf),
+            Input::SymlinkTarget(reader) => reader.read(buf),
+        }
+    }
+}
 
 /// Processes a list of input files or stdin if no files are provided.
 ///
@@ -13,23 +76,337 @@ use std::path::PathBuf;
 pub fn process_inputs<W, F>(files: &[PathBuf], writer: &mut W, f: F) -> anyhow::Result<()>
 where
     W: Write + ?Sized,
-    F: Fn(Box<dyn Read>, &str, &mut W) -> Result<()>,
+    F: Fn(Input, &str, &mut W) -> Result<()>,
+{
+    process_inputs_with(files, &InputOptions::default(), writer, f)
+}
+
+/// Like `process_inputs`, but can walk directory arguments.
+///
+/// With `options.recursive`, every regular file below a directory argument is
+/// processed in sorted order, displayed as the argument joined with its path
+/// inside the directory.
+///
+/// # Arguments
+///
+/// * `files` - A list of file or directory paths. If empty, reads from stdin.
+/// * `options` - How to expand directory arguments.
+/// * `writer` - Output writer.
+/// * `f` - A closure that processes each input stream.
+pub fn process_inputs_with<W, F>(
+    files: &[PathBuf],
+    options: &InputOptions,
+    writer: &mut W,
+    f: F,
+) -> anyhow::Result<()>
+where
+    W: Write + ?Sized,
+    F: Fn(Input, &str, &mut W) -> Result<()>,
 {
     if files.is_empty() {
         let stdin = std::io::stdin();
         let reader = stdin.lock();
-        f(Box::new(reader), "-", writer)?;
+        f(Input::Stdin(reader), "-", writer)?;
     } else {
-        for path in files {
-            if path.is_file() {
-                let file = File::open(path)
+        for source in sources(files, options) {
+            match source?.open()? {
+                Opened::Input(input, path_display) => f(input, &path_display, writer)?,
+                Opened::Skipped(message) => eprintln!("{}", message),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like `process_inputs_with`, but processes up to `jobs` inputs concurrently.
+///
+/// Each input's output is buffered and written in argument order, so the
+/// result is identical to a sequential run. When an input fails, the output
+/// of every input before it is written and its error is returned, exactly as
+/// `process_inputs_with` would. Stdin is always processed on the calling
+/// thread.
+///
+/// # Arguments
+///
+/// * `files` - A list of file or directory paths. If empty, reads from stdin.
+/// * `options` - How to expand directory arguments.
+/// * `jobs` - Number of worker threads; `1` processes inputs sequentially.
+/// * `writer` - Output writer.
+/// * `f` - A closure that processes each input stream.
+pub fn process_inputs_parallel<W, F>(
+    files: &[PathBuf],
+    options: &InputOptions,
+    jobs: usize,
+    writer: &mut W,
+    f: F,
+) -> anyhow::Result<()>
+where
+    W: Write + ?Sized,
+    F: Fn(Input, &str, &mut Vec<u8>) -> Result<()> + Sync,
+{
+    if files.is_empty() || jobs <= 1 {
+        return process_inputs_with(files, options, writer, |input, path_display, writer| {
+            let mut buffer = Vec::new();
+            f(input, path_display, &mut buffer)?;
+            writer.write_all(&buffer)?;
+            Ok(())
+        });
+    }
+
+    let sources = Mutex::new(sources(files, options).enumerate());
+    let cancelled = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (sources, cancelled, f) = (&sources, &cancelled, &f);
+            scope.spawn(move || {
+                while !cancelled.load(Ordering::Relaxed) {
+                    let next = sources.lock().unwrap().next();
+                    let Some((index, source)) = next else {
+                        break;
+                    };
+                    let outcome = source.and_then(|source| source.run(f));
+                    if sender.send((index, outcome)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // Reorder completed inputs so output follows argument order.
+        let mut pending = BTreeMap::new();
+        let mut next_index = 0;
+        for (index, outcome) in receiver {
+            pending.insert(index, outcome);
+            while let Some(outcome) = pending.remove(&next_index) {
+                next_index += 1;
+                match outcome {
+                    Ok(Outcome::Output(buffer)) => writer.write_all(&buffer)?,
+                    Ok(Outcome::Skipped(message)) => eprintln!("{}", message),
+                    Err(e) => {
+                        cancelled.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// A single input found while expanding the command-line arguments.
+pub(crate) enum Source {
+    /// A regular file to open.
+    File(PathBuf),
+    /// A symlink hashed as its target path, see `SymlinkMode::Target`.
+    SymlinkTarget { path: PathBuf, target: Vec<u8> },
+    /// An argument that is skipped, with the message to print on stderr.
+    Skipped(String),
+}
+
+/// A `Source` ready to be processed.
+enum Opened {
+    /// The opened input and its display string.
+    Input(Input, String),
+    /// The message explaining why the source is skipped.
+    Skipped(String),
+}
+
+/// The result of processing a `Source` on a worker thread.
+enum Outcome {
+    /// Output buffered for the input.
+    Output(Vec<u8>),
+    /// The message explaining why the source is skipped.
+    Skipped(String),
+}
+
+impl Source {
+    fn open(self) -> Result<Opened> {
+        match self {
+            Source::File(path) => {
+                let file = File::open(&path)
                     .with_context(|| format!("Failed to open file: {}", path.display()))?;
-                let reader = BufReader::new(file);
-                f(Box::new(reader), &path.display().to_string(), writer)?;
+                let path_display = path.display().to_string();
+                let input = Input::File {
+                    path,
+                    reader: BufReader::new(file),
+                };
+                Ok(Opened::Input(input, path_display))
+            }
+            Source::SymlinkTarget { path, target } => Ok(Opened::Input(
+                Input::SymlinkTarget(Cursor::new(target)),
+                path.display().to_string(),
+            )),
+            Source::Skipped(message) => Ok(Opened::Skipped(message)),
+        }
+    }
+
+    /// Opens the source and runs `f` on it, buffering the output.
+    fn run<F>(self, f: &F) -> Result<Outcome>
+    where
+        F: Fn(Input, &str, &mut Vec<u8>) -> Result<()>,
+    {
+        match self.open()? {
+            Opened::Input(input, path_display) => {
+                let mut buffer = Vec::new();
+                f(input, &path_display, &mut buffer)?;
+                Ok(Outcome::Output(buffer))
+            }
+            Opened::Skipped(message) => Ok(Outcome::Skipped(message)),
+        }
+    }
+}
+
+/// Expands `files` into the inputs to process, in order.
+///
+/// With `options.recursive`, directories are walked in sorted order and every
+/// regular file below them is yielded. Entries that cannot be read are
+/// reported on stderr and skipped.
+pub(crate) fn sources<'a>(
+    files: &'a [PathBuf],
+    options: &'a InputOptions,
+) -> Box<dyn Iterator<Item = Result<Source>> + Send + 'a> {
+    Box::new(files.iter().flat_map(move |path| {
+        let expanded: Box<dyn Iterator<Item = Result<Source>> + Send> = if path.is_file() {
+            Box::new(std::iter::once(Ok(Source::File(path.clone()))))
+        } else if options.recursive && path.is_dir() {
+            Box::new(directory_sources(path, options))
+        } else {
+            Box::new(std::iter::once(Ok(Source::Skipped(format!(
+                "{}: Is a directory",
+                path.display()
+            )))))
+        };
+        expanded
+    }))
+}
+
+/// Walks `root` in sorted order, yielding every regular file below it.
+fn directory_sources(
+    root: &Path,
+    options: &InputOptions,
+) -> impl Iterator<Item = Result<Source>> + Send + use<> {
+    let symlinks = options.symlinks;
+    WalkDir::new(root)
+        .follow_links(symlinks == SymlinkMode::Follow)
+        .same_file_system(options.one_file_system)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(move |entry| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Some(Ok(Source::Skipped(e.to_string()))),
+            };
+            let file_type = entry.file_type();
+            if file_type.is_symlink() {
+                if symlinks != SymlinkMode::Target {
+                    return None;
+                }
+                let target = std::fs::read_link(entry.path())
+                    .with_context(|| format!("Failed to read link: {}", entry.path().display()));
+                Some(target.map(|target| Source::SymlinkTarget {
+                    path: entry.into_path(),
+                    target: target.into_os_string().into_encoded_bytes(),
+                }))
+            } else if file_type.is_file() {
+                Some(Ok(Source::File(entry.into_path())))
             } else {
-                eprintln!("{}: Is a directory", path.display());
+                None
             }
+        })
+}
+
+/// Error returned when a command failed and has already reported why.
+///
+/// The binary exits with a non-zero status without printing anything else.
+#[derive(Debug)]
+pub struct SilentFailure;
+
+impl std::fmt::Display for SilentFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "command failed")
+    }
+}
+
+impl std::error::Error for SilentFailure {}
+
+/// Error of a comparison that could not be carried out, as opposed to one
+/// that found differences.
+///
+/// The binary prints it like any other error but exits with status 2, like
+/// `diff` and `cmp` do on trouble, so scripts can tell the two apart.
+#[derive(Debug)]
+pub struct Trouble(pub anyhow::Error);
+
+impl std::fmt::Display for Trouble {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Trouble {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_files(dir: &Path, count: usize) -> Vec<PathBuf> {
+        (0..count)
+            .map(|i| {
+                let path = dir.join(format!("file_{:03}", i));
+                std::fs::write(&path, i.to_string()).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn echo(mut input: Input, path_display: &str, writer: &mut Vec<u8>) -> Result<()> {
+        let mut content = String::new();
+        input.read_to_string(&mut content)?;
+        if path_display.ends_with("bad") {
+            anyhow::bail!("bad input");
+        }
+        writeln!(writer, "{} {}", path_display, content)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_preserves_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = write_files(dir.path(), 50);
+
+        let mut sequential = Vec::new();
+        process_inputs_parallel(&files, &InputOptions::default(), 1, &mut sequential, echo)
+            .unwrap();
+        let mut parallel = Vec::new();
+        process_inputs_parallel(&files, &InputOptions::default(), 8, &mut parallel, echo).unwrap();
+
+        assert_eq!(sequential, parallel);
+        assert_eq!(String::from_utf8(parallel).unwrap().lines().count(), 50);
+    }
+
+    #[test]
+    fn test_parallel_stops_at_first_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = write_files(dir.path(), 20);
+        let bad = dir.path().join("bad");
+        std::fs::write(&bad, "").unwrap();
+        files.insert(5, bad);
+
+        for jobs in [1, 4] {
+            let mut writer = Vec::new();
+            let result =
+                process_inputs_parallel(&files, &InputOptions::default(), jobs, &mut writer, echo);
+            assert!(result.is_err());
+            let output = String::from_utf8(writer).unwrap();
+            assert_eq!(output.lines().count(), 5, "jobs = {}", jobs);
         }
     }
-    Ok(())
 }
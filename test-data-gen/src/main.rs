@@ -60,7 +60,7 @@ fn main() {
         }
 
         large_json.truncate(large_json.len() - 1);
-        large_json.push_str("]");
+        large_json.push(']');
         create_file_if_missing(&large_json_path, large_json.as_bytes());
 
         let prettier_output_path = output_dir.join("large_file_prettier.json");
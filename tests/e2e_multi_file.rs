@@ -79,3 +79,141 @@ fn test_filter_directories() {
     let lines: Vec<&str> = stdout.trim().split('\n').collect();
     assert_eq!(lines.len(), 1, "Expected exactly one line of output");
 }
+
+#[test]
+fn test_checksum_recursive() {
+    let bin_path = env!("CARGO_BIN_EXE_my_app");
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("tree");
+    fs::create_dir_all(root.join("b/nested")).unwrap();
+    fs::write(root.join("c.txt"), "c").unwrap();
+    fs::write(root.join("a.txt"), "a").unwrap();
+    fs::write(root.join("b/nested/d.txt"), "d").unwrap();
+
+    let output = Command::new(bin_path)
+        .arg("--checksum")
+        .arg(&root)
+        .arg("--recursive")
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let paths: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.split_once("  ").unwrap().1)
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            root.join("a.txt").display().to_string(),
+            root.join("b/nested/d.txt").display().to_string(),
+            root.join("c.txt").display().to_string(),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_checksum_recursive_symlinks() {
+    let bin_path = env!("CARGO_BIN_EXE_my_app");
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("tree");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("file.txt"), "hello").unwrap();
+    std::os::unix::fs::symlink("file.txt", root.join("link.txt")).unwrap();
+    // A link back to the root must not make the walk loop forever
+    std::os::unix::fs::symlink(&root, root.join("loop")).unwrap();
+
+    let run = |mode: &str| {
+        let output = Command::new(bin_path)
+            .arg("--checksum")
+            .arg(&root)
+            .args(["--recursive", "--symlinks", mode])
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let hello = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    let link_display = root.join("link.txt").display().to_string();
+
+    let follow = run("follow");
+    assert!(follow.contains(&format!("{}  {}", hello, link_display)));
+
+    let skip = run("skip");
+    assert!(!skip.contains(&link_display));
+    assert_eq!(skip.lines().count(), 1);
+
+    // sha256 of the link target string "file.txt"
+    let target = run("target");
+    assert!(target.contains(&format!(
+        "{}  {}",
+        "aadf327c8267c09d6fffd87a1a80ad3c798469ff332b7a57b9e8c045d46b2af7", link_display
+    )));
+}
+
+#[test]
+fn test_checksum_tree() {
+    let bin_path = env!("CARGO_BIN_EXE_my_app");
+    let first = tempfile::tempdir().unwrap();
+    let second = tempfile::tempdir().unwrap();
+    for root in [first.path(), second.path()] {
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("sub/b.txt"), "b").unwrap();
+    }
+
+    let tree_digest = |root: &std::path::Path| {
+        let output = Command::new(bin_path)
+            .arg("--checksum")
+            .arg(root)
+            .arg("--tree")
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(
+            stdout.lines().count(),
+            1,
+            "Expected exactly one line of output"
+        );
+        let (digest, path) = stdout.trim_end().split_once("  ").unwrap();
+        assert_eq!(path, root.display().to_string());
+        digest.to_string()
+    };
+
+    assert_eq!(tree_digest(first.path()), tree_digest(second.path()));
+    fs::write(second.path().join("sub/b.txt"), "changed").unwrap();
+    assert_ne!(tree_digest(first.path()), tree_digest(second.path()));
+}
+
+#[test]
+fn test_checksum_jobs_preserve_order() {
+    let bin_path = env!("CARGO_BIN_EXE_my_app");
+    let dir = tempfile::tempdir().unwrap();
+    let files: Vec<PathBuf> = (0..100)
+        .map(|i| {
+            let path = dir.path().join(format!("file_{}.txt", i));
+            fs::write(&path, "x".repeat(i * 1000)).unwrap();
+            path
+        })
+        .collect();
+
+    let run = |jobs: &str| {
+        let output = Command::new(bin_path)
+            .arg("--checksum")
+            .args(&files)
+            .args(["--jobs", jobs])
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let sequential = run("1");
+    assert_eq!(sequential.lines().count(), 100);
+    assert_eq!(run("8"), sequential);
+    assert_eq!(run("0"), sequential);
+}
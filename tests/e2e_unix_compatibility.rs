@@ -3,8 +3,9 @@ use std::io::Write;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 
-#[test]
-fn test_sha256sum_compatibility() {
+/// Checks that `my_app --checksum <file> <args>` prints exactly what
+/// `tool <tool_args> <file>` prints.
+fn assert_coreutils_compatibility(tool: &str, tool_args: &[&str], args: &[&str]) {
     let test_dir =
         PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/test-data-gen/compat_test_dir");
     // Iterate over existing files in compat_test_dir
@@ -21,25 +22,28 @@ fn test_sha256sum_compatibility() {
 
         let input_path_str = input_path.to_str().unwrap();
 
-        let sha256_output = Command::new("sha256sum")
+        let tool_output = match Command::new(tool)
+            .args(tool_args)
             .arg(input_path_str)
             .output()
-            .expect("Failed to execute sha256sum");
-
-        if !sha256_output.status.success() {
-            eprintln!(
-                "sha256sum command failed or not found, skipping compatibility test for {:?}",
-                input_path
-            );
-            continue;
-        }
+        {
+            Ok(output) if output.status.success() => output,
+            _ => {
+                eprintln!(
+                    "{} command failed or not found, skipping compatibility test for {:?}",
+                    tool, input_path
+                );
+                continue;
+            }
+        };
 
-        let sha256_stdout = String::from_utf8(sha256_output.stdout).unwrap();
+        let tool_stdout = String::from_utf8(tool_output.stdout).unwrap();
 
         // Run my_app
         let my_app_output = Command::new(env!("CARGO_BIN_EXE_my_app"))
             .arg("--checksum")
             .arg(input_path_str)
+            .args(args)
             .output()
             .expect("Failed to execute my_app");
 
@@ -53,14 +57,85 @@ fn test_sha256sum_compatibility() {
         // Compare outputs
         assert_eq!(
             my_app_stdout,
-            sha256_stdout,
-            "Output mismatch for file {:?} (size {})",
+            tool_stdout,
+            "Output mismatch with {} for file {:?} (size {})",
+            tool,
             input_path,
             entry.metadata().unwrap().len()
         );
     }
 }
 
+#[test]
+fn test_sha256sum_compatibility() {
+    assert_coreutils_compatibility("sha256sum", &[], &["--algo", "sha256"]);
+}
+
+#[test]
+fn test_sha224sum_compatibility() {
+    assert_coreutils_compatibility("sha224sum", &[], &["--algo", "sha224"]);
+}
+
+#[test]
+fn test_sha384sum_compatibility() {
+    assert_coreutils_compatibility("sha384sum", &[], &["--algo", "sha384"]);
+}
+
+#[test]
+fn test_sha512sum_compatibility() {
+    assert_coreutils_compatibility("sha512sum", &[], &["--algo", "sha512"]);
+}
+
+#[test]
+fn test_sha256sum_tag_compatibility() {
+    assert_coreutils_compatibility("sha256sum", &["--tag"], &["--tag"]);
+}
+
+#[test]
+fn test_sha512sum_tag_compatibility() {
+    assert_coreutils_compatibility("sha512sum", &["--tag"], &["--algo", "sha512", "--tag"]);
+}
+
+#[test]
+fn test_md5sum_compatibility() {
+    assert_coreutils_compatibility("md5sum", &[], &["--algo", "md5", "--allow-weak-hashes"]);
+}
+
+#[test]
+fn test_sha1sum_compatibility() {
+    assert_coreutils_compatibility("sha1sum", &[], &["--algo", "sha1", "--allow-weak-hashes"]);
+}
+
+#[test]
+fn test_weak_hash_requires_opt_in() {
+    let checksum_txt =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/test-data-gen/checksum.txt");
+
+    let refused = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg(&checksum_txt)
+        .arg("--algo")
+        .arg("md5")
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(!refused.status.success());
+    assert!(refused.stdout.is_empty());
+    let stderr = String::from_utf8(refused.stderr).unwrap();
+    assert!(stderr.contains("--allow-weak-hashes"));
+
+    let allowed = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg(&checksum_txt)
+        .arg("--algo")
+        .arg("md5")
+        .arg("--allow-weak-hashes")
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(allowed.status.success());
+    let stderr = String::from_utf8(allowed.stderr).unwrap();
+    assert!(stderr.contains("WARNING: md5"));
+}
+
 #[test]
 fn test_pipe_checksum_valid() {
     let bin_path = env!("CARGO_BIN_EXE_my_app");
@@ -104,3 +179,481 @@ fn test_pipe_checksum_valid() {
 
     assert_eq!(stdout, sha256sum_stdout);
 }
+
+#[test]
+fn test_check_manifest_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_a = dir.path().join("a.txt");
+    let file_b = dir.path().join("b.txt");
+    fs::write(&file_a, "hello").unwrap();
+    fs::write(&file_b, "world").unwrap();
+
+    // Manifest written by my_app, verified by both sha256sum and my_app
+    let manifest = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg(&file_a)
+        .arg(&file_b)
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(manifest.status.success());
+    let manifest_path = dir.path().join("SHA256SUMS");
+    fs::write(&manifest_path, &manifest.stdout).unwrap();
+
+    if let Ok(sha256sum) = Command::new("sha256sum")
+        .arg("--check")
+        .arg(&manifest_path)
+        .output()
+    {
+        assert!(sha256sum.status.success());
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg("--check")
+        .arg(&manifest_path)
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}: OK\n{}: OK\n", file_a.display(), file_b.display())
+    );
+
+    // A modified file is reported and fails the command
+    fs::write(&file_b, "tampered").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg("--check")
+        .arg(&manifest_path)
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains(&format!("{}: FAILED", file_b.display()))
+    );
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("WARNING: 1 computed checksum did NOT match")
+    );
+
+    // --status reports only through the exit code
+    let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg("--check")
+        .arg(&manifest_path)
+        .arg("--status")
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_check_tagged_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_a = dir.path().join("a.txt");
+    let file_b = dir.path().join("b.txt");
+    fs::write(&file_a, "hello").unwrap();
+    fs::write(&file_b, "world").unwrap();
+
+    // One BSD SHA512 line and one GNU SHA256 line in the same manifest
+    let tagged = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg(&file_a)
+        .args(["--algo", "sha512", "--tag"])
+        .output()
+        .expect("Failed to execute my_app");
+    let plain = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg(&file_b)
+        .output()
+        .expect("Failed to execute my_app");
+    let manifest_path = dir.path().join("CHECKSUMS");
+    fs::write(&manifest_path, [tagged.stdout, plain.stdout].concat()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg("--check")
+        .arg(&manifest_path)
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}: OK\n{}: OK\n", file_a.display(), file_b.display())
+    );
+}
+
+#[test]
+fn test_check_sha256sum_manifest() {
+    let test_dir =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/test-data-gen/compat_test_dir");
+    let files: Vec<PathBuf> = fs::read_dir(test_dir)
+        .expect("Failed to read compat_test_dir. Did you run 'nix run .#prepare_tests'?")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+
+    let Ok(sha256sum) = Command::new("sha256sum").args(&files).output() else {
+        eprintln!("sha256sum not found, skipping manifest compatibility test");
+        return;
+    };
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg("--check")
+        .arg("--quiet")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn command");
+    child
+        .stdin
+        .as_mut()
+        .expect("Failed to open stdin")
+        .write_all(&sha256sum.stdout)
+        .expect("Failed to write to stdin");
+    let output = child.wait_with_output().expect("Failed to read output");
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_checksum_multiple_algorithms_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_a = dir.path().join("a.txt");
+    let file_b = dir.path().join("b.txt");
+    fs::write(&file_a, "hello").unwrap();
+    fs::write(&file_b, "world").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .args([&file_a, &file_b])
+        .args(["--algo", "sha256,sha512", "--algo", "blake3"])
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let tags: Vec<_> = stdout
+        .lines()
+        .map(|line| line.split(' ').next().unwrap())
+        .collect();
+    assert_eq!(
+        tags,
+        ["SHA256", "SHA512", "BLAKE3", "SHA256", "SHA512", "BLAKE3"]
+    );
+
+    let manifest_path = dir.path().join("CHECKSUMS");
+    fs::write(&manifest_path, &stdout).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg("--check")
+        .arg(&manifest_path)
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 6);
+}
+
+#[test]
+fn test_checksum_keyed_roundtrip_never_prints_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    let key_file = dir.path().join("key");
+    fs::write(&file, "hello").unwrap();
+    fs::write(&key_file, "correct horse battery staple\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg(&file)
+        .arg("--key-file")
+        .arg(&key_file)
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(output.status.success());
+    let manifest_path = dir.path().join("CHECKSUMS");
+    fs::write(&manifest_path, &output.stdout).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg("--check")
+        .arg(&manifest_path)
+        .arg("--key-env")
+        .arg("MY_APP_TEST_KEY")
+        .env("MY_APP_TEST_KEY", "correct horse battery staple")
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(output.status.success());
+
+    // Neither an unsuitable algorithm nor a bad keyed BLAKE3 key shows the key
+    for algo in ["md5", "blake3"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+            .arg("--checksum")
+            .arg(&file)
+            .args([
+                "--algo",
+                algo,
+                "--allow-weak-hashes",
+                "--key-env",
+                "MY_APP_TEST_KEY",
+            ])
+            .env("MY_APP_TEST_KEY", "correct horse battery staple")
+            .output()
+            .expect("Failed to execute my_app");
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(!stderr.is_empty());
+        assert!(!stderr.contains("correct horse"), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_checksum_escaped_and_zero_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("new\nline\\slash.txt");
+    fs::write(&file, "hello").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg(&file)
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with('\\'));
+    assert!(stdout.ends_with("new\\nline\\\\slash.txt\n"));
+    assert_eq!(stdout.lines().count(), 1);
+
+    // Same line as GNU sha256sum, if it is installed
+    if let Ok(gnu) = Command::new("sha256sum").arg(&file).output()
+        && gnu.status.success()
+    {
+        assert_eq!(String::from_utf8(gnu.stdout).unwrap(), stdout);
+    }
+
+    for zero in [false, true] {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_my_app"));
+        command.arg("--checksum").arg(&file);
+        if zero {
+            command.arg("--zero");
+        }
+        let manifest = command.output().expect("Failed to execute my_app").stdout;
+        assert_eq!(manifest.last(), Some(if zero { &b'\0' } else { &b'\n' }));
+        let manifest_path = dir.path().join("SUMS");
+        fs::write(&manifest_path, &manifest).unwrap();
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_my_app"));
+        command.arg("--checksum").arg("--check").arg(&manifest_path);
+        if zero {
+            command.arg("-z");
+        }
+        let output = command.output().expect("Failed to execute my_app");
+        assert!(output.status.success(), "zero: {}", zero);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!(
+                "\\{}: OK\n",
+                dir.path().join("new\\nline\\\\slash.txt").display()
+            )
+        );
+    }
+}
+
+#[test]
+fn test_signed_manifest_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    let key = dir.path().join("release.key");
+    let public_key = dir.path().join("release.key.pub");
+    let manifest_path = dir.path().join("SUMS");
+    fs::write(&file, "hello").unwrap();
+
+    let run = |args: &[&std::ffi::OsStr]| {
+        Command::new(env!("CARGO_BIN_EXE_my_app"))
+            .args(args)
+            .output()
+            .expect("Failed to execute my_app")
+    };
+    assert!(run(&["--keygen".as_ref(), key.as_ref()]).status.success());
+    let output = run(&["--checksum".as_ref(), file.as_ref()]);
+    assert!(output.status.success());
+    fs::write(&manifest_path, &output.stdout).unwrap();
+    assert!(
+        run(&["--sign".as_ref(), key.as_ref(), manifest_path.as_ref()])
+            .status
+            .success()
+    );
+    let output = run(&[
+        "--verify-signature".as_ref(),
+        public_key.as_ref(),
+        manifest_path.as_ref(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}: signature OK\n", manifest_path.display())
+    );
+
+    let check = || {
+        run(&[
+            "--checksum".as_ref(),
+            "--check".as_ref(),
+            manifest_path.as_ref(),
+            "--signed-by".as_ref(),
+            public_key.as_ref(),
+        ])
+    };
+    let output = check();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}: OK\n", file.display())
+    );
+
+    // Options of other commands are rejected rather than ignored
+    let output = run(&["--keygen".as_ref(), key.as_ref(), "--tree".as_ref()]);
+    assert!(!output.status.success());
+
+    // A tampered manifest is rejected before any listed file is read
+    let mut manifest = fs::read_to_string(&manifest_path).unwrap();
+    manifest.push_str(&manifest.replace("a.txt", "b.txt"));
+    fs::write(&manifest_path, manifest).unwrap();
+    let output = check();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}: signature FAILED\n", manifest_path.display())
+    );
+}
+
+#[test]
+fn test_checksum_range_from_file_and_stdin() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("data.bin");
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(&file, &data).unwrap();
+    let range = [
+        "--offset",
+        "1000",
+        "--length",
+        "5000",
+        "--chunk-size",
+        "2048",
+    ];
+
+    // Seeking in the file and discarding from stdin reach the same bytes
+    let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .arg(&file)
+        .args(range)
+        .output()
+        .expect("Failed to execute my_app");
+    assert!(output.status.success());
+    let from_file = String::from_utf8(output.stdout).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_my_app"))
+        .arg("--checksum")
+        .args(range)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn my_app");
+    child.stdin.take().unwrap().write_all(&data).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let from_stdin = String::from_utf8(output.stdout).unwrap();
+
+    let chunks: Vec<_> = from_file
+        .lines()
+        .map(|line| line.split_once("  ").unwrap())
+        .collect();
+    assert_eq!(
+        chunks.iter().map(|(_, path)| *path).collect::<Vec<_>>(),
+        [
+            format!("{}@1000+2048", file.display()),
+            format!("{}@3048+2048", file.display()),
+            format!("{}@5096+904", file.display()),
+        ]
+    );
+    let stdin_chunks: Vec<_> = from_stdin
+        .lines()
+        .map(|line| line.split_once("  ").unwrap())
+        .collect();
+    assert_eq!(stdin_chunks.len(), 3);
+    for ((file_digest, _), (stdin_digest, path)) in chunks.iter().zip(&stdin_chunks) {
+        assert_eq!(file_digest, stdin_digest);
+        assert!(path.starts_with("-@"));
+    }
+}
+
+#[test]
+fn test_manifest_diff_exit_codes() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = dir.path().join("old.sums");
+    let new = dir.path().join("new.sums");
+    let a = "a".repeat(64);
+    let b = "b".repeat(64);
+    fs::write(&old, format!("{a}  kept\n{a}  changed\n")).unwrap();
+    fs::write(&new, format!("{a}  kept\n{b}  changed\n")).unwrap();
+    let diff = |old: &PathBuf, new: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_my_app"))
+            .arg("--checksum")
+            .arg("--manifest-diff")
+            .arg(old)
+            .arg(new)
+            .output()
+            .expect("Failed to execute my_app")
+    };
+
+    // Identical manifests
+    let output = diff(&old, &old);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    // Differences are printed and exit with 1
+    let output = diff(&old, &new);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "modified  changed\n"
+    );
+    assert!(output.stderr.is_empty());
+
+    // A missing manifest or one without a valid line is trouble, like diff(1)
+    let output = diff(&old, &dir.path().join("missing.sums"));
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Failed to open file")
+    );
+    let garbage = dir.path().join("garbage.sums");
+    fs::write(&garbage, "not a checksum line\n").unwrap();
+    let output = diff(&garbage, &new);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    // So are a refused algorithm and an unreadable key
+    for extra in [
+        ["--algo", "md5"],
+        [
+            "--key-file",
+            dir.path().join("missing.key").to_str().unwrap(),
+        ],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_my_app"))
+            .arg("--checksum")
+            .arg("--manifest-diff")
+            .arg(&old)
+            .arg(&new)
+            .args(extra)
+            .output()
+            .expect("Failed to execute my_app");
+        assert_eq!(output.status.code(), Some(2), "{:?}", extra);
+    }
+}